@@ -0,0 +1,198 @@
+//! Measures the systems that run the simulation every tick, in a headless world without any rendering,
+//! so that performance regressions show up before they show up as dropped frames.
+
+use std::time::Duration;
+
+use bevy::{prelude::*, asset::AssetPlugin, core::CorePlugin, ecs::event::Events};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use bevy_playground::{
+	pid_controller::{PidControlled, PidControlledRotation},
+	tower_defense::{
+		enemy::{self, Enemy, EnemyCreateOptions, EventEnemyHurt},
+		exp_level::{ExpLevel, ExperienceBus},
+		map::{self, PathInterpolation, PathLookup},
+		motion::TrackedVelocity,
+		spatial::{self, SpatialHash},
+		towers::{self, projectile::{self, TowerProjectile}, Aim, Tower},
+	},
+};
+
+const ENEMY_COUNTS: [usize; 2] = [100, 1000];
+const TOWERS: usize = 100;
+const PROJECTILES: usize = 500;
+/// How long each simulated tick lasts.
+const FRAME_TIME: Duration = Duration::from_millis(16);
+
+/// A long zigzag, so that enemies don't reach the end of it while the benchmarks run.
+fn long_path(interpolation: PathInterpolation) -> map::Path {
+	let points = (0..100)
+		.map(|i| Vec3::new(i as f32 * 5. - 250., if i % 2 == 0 { -15. } else { 15. }, 0.))
+		.collect();
+	map::Path::new(0, points).with_interpolation(interpolation)
+}
+
+fn random_position(rng: &mut StdRng) -> Vec3 {
+	Vec3::new(rng.gen_range(-250.0..250.0), rng.gen_range(-15.0..15.0), rng.gen_range(0.0..5.0))
+}
+
+/// `Time` can only be advanced by actually waiting, so wait for one tick once and hand out copies of the result.
+fn one_frame_later() -> Time {
+	let mut time = Time::default();
+	time.update();
+	std::thread::sleep(FRAME_TIME);
+	time.update();
+	time
+}
+
+/// A world with everything the simulation systems need, but no window or renderer.
+/// Enemies are spread over the first half of [`long_path`], and towers are scattered around it.
+fn headless_app(enemies: usize, towers: usize, time: &Time) -> App {
+	let mut app = App::new();
+	app.add_plugin(CorePlugin)
+		.add_plugin(AssetPlugin)
+		.add_asset::<Mesh>()
+		.add_asset::<StandardMaterial>()
+		.insert_resource(time.clone())
+		.insert_resource(ExperienceBus::new())
+		.insert_resource(Events::<EventEnemyHurt>::default())
+		.init_resource::<SpatialHash>()
+		.init_resource::<PathLookup>();
+
+	let path = long_path(PathInterpolation::Linear);
+	let mut rng = StdRng::seed_from_u64(0);
+	for _ in 0..enemies {
+		let mut enemy = Enemy::new(EnemyCreateOptions {
+			health: 100,
+			speed: 1.,
+			path_id: path.id,
+			archetype: Default::default(),
+			movement: Default::default(),
+		});
+		enemy.path_pos = rng.gen_range(0.0..path.total_length() / 2.);
+		enemy.distance_to_exit = path.distance_to_exit(enemy.path_pos);
+		app.world.spawn()
+			.insert(Transform::from_translation(path.get_point_along_path(enemy.path_pos)))
+			.insert(enemy)
+			.insert(TrackedVelocity::default());
+	}
+	for _ in 0..towers {
+		app.world.spawn()
+			.insert(Tower::new())
+			.insert(ExpLevel::new())
+			.insert(PidControlledRotation::<Aim>::new(10., 0., 0., std::f32::consts::PI))
+			.insert(Transform::from_translation(random_position(&mut rng)));
+	}
+	app.world.spawn().insert(path);
+
+	SystemStage::single_threaded()
+		.with_system(map::update_path_lookup)
+		.with_system(spatial::update_enemy_hash)
+		.run(&mut app.world);
+	app
+}
+
+/// Adds guided and ballistic projectiles that are on their way to random enemies.
+fn spawn_projectiles(world: &mut World, count: usize) {
+	let mut rng = StdRng::seed_from_u64(1);
+	let targets: Vec<(Entity, Vec3)> = world.query_filtered::<(Entity, &Transform), With<Enemy>>()
+		.iter(world)
+		.map(|(entity, transform)| (entity, transform.translation))
+		.collect();
+	for i in 0..count {
+		let (target, target_position) = targets[rng.gen_range(0..targets.len())];
+		let position = target_position + Vec3::new(rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0), 3.);
+		let projectile = if i % 4 == 0 {
+			TowerProjectile::ballistic(25, target, position, target_position, 1., 2.)
+		} else {
+			TowerProjectile::guided(15, target, (target_position - position).normalize() * 5.)
+		};
+		world.spawn()
+			.insert(projectile)
+			.insert(Transform::from_translation(position));
+	}
+}
+
+fn bench_operate_towers(c: &mut Criterion) {
+	// time doesn't advance, so towers only pick targets without attacking
+	let time = Time::default();
+	let mut group = c.benchmark_group("operate_towers");
+	for enemies in ENEMY_COUNTS {
+		let mut app = headless_app(enemies, TOWERS, &time);
+		let mut stage = SystemStage::single_threaded()
+			.with_system(spatial::update_enemy_hash)
+			.with_system(towers::operate_towers.after(spatial::update_enemy_hash));
+		group.bench_function(BenchmarkId::new(format!("{}_towers", TOWERS), enemies), |b| {
+			b.iter(|| stage.run(&mut app.world))
+		});
+	}
+	group.finish();
+}
+
+fn bench_move_enemies(c: &mut Criterion) {
+	let time = one_frame_later();
+	let mut group = c.benchmark_group("move_enemies");
+	for enemies in ENEMY_COUNTS {
+		let mut app = headless_app(enemies, 0, &time);
+		let mut stage = SystemStage::single_threaded().with_system(enemy::move_enemies);
+		group.bench_function(BenchmarkId::from_parameter(enemies), |b| b.iter(|| stage.run(&mut app.world)));
+	}
+	group.finish();
+}
+
+fn bench_projectiles(c: &mut Criterion) {
+	let time = one_frame_later();
+	let mut group = c.benchmark_group("move_projectiles");
+	for enemies in ENEMY_COUNTS {
+		// projectiles are used up when they hit, so every iteration gets a fresh world
+		group.bench_function(BenchmarkId::new(format!("{}_projectiles", PROJECTILES), enemies), |b| {
+			b.iter_batched_ref(
+				|| {
+					let mut app = headless_app(enemies, 0, &time);
+					spawn_projectiles(&mut app.world, PROJECTILES);
+					let stage = SystemStage::single_threaded()
+						.with_system(projectile::move_projectiles)
+						.with_system(projectile::projectile_collisions.after(projectile::move_projectiles));
+					(app, stage)
+				},
+				|(app, stage)| stage.run(&mut app.world),
+				BatchSize::LargeInput,
+			)
+		});
+	}
+	group.finish();
+}
+
+fn bench_path_points(c: &mut Criterion) {
+	let mut group = c.benchmark_group("get_point_along_path");
+	for (name, interpolation) in [("linear", PathInterpolation::Linear), ("catmull_rom", PathInterpolation::CatmullRom)] {
+		let path = long_path(interpolation);
+		let distances: Vec<f32> = (0..1000).map(|i| path.total_length() * i as f32 / 1000.).collect();
+		group.bench_function(name, |b| b.iter(|| {
+			distances.iter()
+				.map(|distance| path.get_point_along_path(black_box(*distance)))
+				.fold(Vec3::ZERO, |sum, point| sum + point)
+		}));
+	}
+	group.finish();
+}
+
+fn bench_pid_compute(c: &mut Criterion) {
+	let delta = FRAME_TIME.as_secs_f32();
+	let mut controllers: Vec<PidControlled<Vec3, ()>> = (0..1000)
+		.map(|i| {
+			let mut controller = PidControlled::new(0.1, 0.01, 0.01);
+			controller.set_target(Vec3::new(i as f32, 0., 1.));
+			controller
+		})
+		.collect();
+	c.bench_function("pid_compute/1000_vec3", |b| b.iter(|| {
+		controllers.iter_mut()
+			.map(|controller| controller.compute(delta, black_box(Vec3::ONE)))
+			.fold(Vec3::ZERO, |sum, output| sum + output)
+	}));
+}
+
+criterion_group!(benches, bench_operate_towers, bench_move_enemies, bench_projectiles, bench_path_points, bench_pid_compute);
+criterion_main!(benches);
@@ -0,0 +1,49 @@
+//! Compares finding enemies in range with the spatial hash against checking every enemy,
+//! at the size of the biggest wave: 1000 enemies against 100 towers.
+
+use bevy::prelude::*;
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use bevy_playground::tower_defense::{spatial::SpatialHash, towers::Tower};
+
+const ENEMIES: usize = 1000;
+const TOWERS: usize = 100;
+
+fn random_positions(rng: &mut StdRng, count: usize) -> Vec<Vec3> {
+	(0..count)
+		.map(|_| Vec3::new(rng.gen_range(-30.0..30.0), rng.gen_range(-15.0..15.0), rng.gen_range(0.0..5.0)))
+		.collect()
+}
+
+fn bench_range_queries(c: &mut Criterion) {
+	let mut rng = StdRng::seed_from_u64(0);
+	let enemies: Vec<(Entity, Vec3)> = random_positions(&mut rng, ENEMIES).into_iter()
+		.enumerate()
+		.map(|(i, position)| (Entity::from_raw(i as u32), position))
+		.collect();
+	let towers = random_positions(&mut rng, TOWERS);
+	let range = Tower::new().range;
+
+	let mut group = c.benchmark_group("enemies_in_range");
+	group.bench_function("brute_force", |b| b.iter(|| {
+		towers.iter()
+			.map(|tower| enemies.iter().filter(|(_, enemy)| enemy.distance(*tower) <= range).count())
+			.sum::<usize>()
+	}));
+	// rebuilt every iteration like `update_enemy_hash` does every tick, reusing its memory
+	let mut hash = SpatialHash::default();
+	group.bench_function("spatial_hash", |b| b.iter(|| {
+		hash.clear();
+		for (entity, position) in enemies.iter() {
+			hash.insert(*entity, *position);
+		}
+		towers.iter()
+			.map(|tower| hash.query_radius(*tower, range).count())
+			.sum::<usize>()
+	}));
+	group.finish();
+}
+
+criterion_group!(benches, bench_range_queries);
+criterion_main!(benches);
@@ -0,0 +1,86 @@
+//! Finds PID gains for a simulated turret with relay feedback auto-tuning, then tries them out on a step.
+//! The turret stays wherever it is left, so the rules with an integral term overshoot, and the PD gains work best.
+//!
+//! Run with `cargo run --example pid_autotune`.
+
+use bevy_playground::pid_controller::{tuning::{PidGains, RelayAutotune}, PidControlled};
+
+/// A turret with a heavy barrel, that turns by applying torque through a motor that takes a moment to respond.
+#[derive(Debug, Default)]
+struct Turret {
+	/// Radians.
+	angle: f32,
+	/// Radians per second.
+	angular_velocity: f32,
+	torque: f32,
+}
+
+impl Turret {
+	const MOTOR_LAG: f32 = 0.1;
+	const INERTIA: f32 = 2.;
+	const FRICTION: f32 = 1.;
+	/// The most torque that the motor can put out.
+	const MAX_TORQUE: f32 = 2.;
+
+	fn step(&mut self, command: f32, delta_time: f32) -> f32 {
+		self.torque += (command - self.torque) * delta_time / Self::MOTOR_LAG;
+		let acceleration = (self.torque - Self::FRICTION * self.angular_velocity) / Self::INERTIA;
+		self.angular_velocity += acceleration * delta_time;
+		self.angle += self.angular_velocity * delta_time;
+		self.angle
+	}
+}
+
+/// How far past a one radian step the turret swings, and how long until it stays within 2% of it.
+fn step_response(gains: PidGains) -> (f32, Option<f32>) {
+	const DELTA_TIME: f32 = 1. / 60.;
+	const DURATION: f32 = 20.;
+	let mut turret = Turret::default();
+	let mut controller = PidControlled::<f32, Turret>::new(gains.proportional, gains.integral, gains.derivative)
+		.with_output_limit(Turret::MAX_TORQUE);
+	controller.set_target(1.);
+	let mut overshoot: f32 = 0.;
+	let mut settled_at = None;
+	let mut time = 0.;
+	while time < DURATION {
+		let command = controller.compute(DELTA_TIME, turret.angle);
+		let angle = turret.step(command, DELTA_TIME);
+		time += DELTA_TIME;
+		overshoot = overshoot.max(angle - 1.);
+		if (angle - 1.).abs() > 0.02 {
+			settled_at = None;
+		} else if settled_at.is_none() {
+			settled_at = Some(time);
+		}
+	}
+	(overshoot, settled_at)
+}
+
+fn main() {
+	let mut turret = Turret::default();
+	let autotune = RelayAutotune {
+		setpoint: 0.5,
+		relay_output: Turret::MAX_TORQUE,
+		..Default::default()
+	};
+	let result = match autotune.run(|command, delta_time| turret.step(command, delta_time)) {
+		Some(result) => result,
+		None => {
+			println!("The turret never settled into an oscillation, try a stronger relay or a longer max time");
+			return;
+		}
+	};
+	println!("ultimate gain: {:.3}, ultimate period: {:.3}s", result.ultimate_gain, result.ultimate_period);
+
+	for (name, gains) in [("classic", result.classic_pid()), ("no overshoot", result.no_overshoot()), ("pd", result.pd())] {
+		let (overshoot, settled_at) = step_response(gains);
+		println!(
+			"{}: PidControlled::new({:.3}, {:.3}, {:.3})",
+			name, gains.proportional, gains.integral, gains.derivative,
+		);
+		match settled_at {
+			Some(time) => println!("  overshoot: {:.1}%, settles in {:.2}s", overshoot * 100., time),
+			None => println!("  overshoot: {:.1}%, never settles", overshoot * 100.),
+		}
+	}
+}
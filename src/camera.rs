@@ -1,110 +1,908 @@
-/// Tags an entity as capable of panning and orbiting.
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
 use bevy::prelude::*;
-use bevy::input::mouse::{MouseMotion, MouseWheel};
+use bevy::ecs::event::{Events, ManualEventReader};
+use bevy::input::{mouse::MouseMotion, touch::Touches};
+use bevy::render::camera::{DepthCalculation, RenderTarget, ScalingMode};
+use serde::{Deserialize, Serialize};
+
+use crate::input::{Action, ActionState, InputActionPlugin};
+
+/// Radians per second that the camera orbits while a key is held.
+const KEY_ORBIT_SPEED: f32 = PI / 2.;
+/// How many times the distance to the focus point the camera pans per second while a key is held.
+const KEY_PAN_SPEED: f32 = 1.;
+/// How quickly the camera zooms while a key is held. The distance shrinks by a factor of `e` every `1 / KEY_ZOOM_SPEED` seconds.
+const KEY_ZOOM_SPEED: f32 = 1.5;
+/// How close in pixels the cursor has to be to the edge of the window to scroll an [`CameraMode::Rts`] camera.
+const EDGE_SCROLL_MARGIN: f32 = 10.;
+/// How far above the focus point a [`CameraMode::TopDown`] camera is. Zooming scales the view instead of moving it.
+const TOP_DOWN_DISTANCE: f32 = 100.;
+
+/// Where a [`PanOrbitCamera`] is looking from.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraView {
+	/// The point to orbit around.
+	pub focus: Vec3,
+	/// Distance from the focus point.
+	pub radius: f32,
+	/// Radians around the camera's up axis.
+	pub yaw: f32,
+	/// Radians up or down from level. Negative looks down on the focus point from above.
+	pub pitch: f32,
+}
+
+impl Default for CameraView {
+	fn default() -> Self {
+		Self {
+			focus: Vec3::ZERO,
+			radius: 5.0,
+			yaw: 0.,
+			pitch: 0.,
+		}
+	}
+}
+
+impl CameraView {
+	/// `t` of the way from `self` to `other`, turning the short way around.
+	pub fn lerp(&self, other: &CameraView, t: f32) -> CameraView {
+		CameraView {
+			focus: self.focus.lerp(other.focus, t),
+			radius: self.radius + (other.radius - self.radius) * t,
+			yaw: self.yaw + shortest_angle(self.yaw, other.yaw) * t,
+			pitch: self.pitch + (other.pitch - self.pitch) * t,
+		}
+	}
+
+	fn is_close_to(&self, other: &CameraView) -> bool {
+		self.focus.distance(other.focus) < 0.001
+			&& (self.radius - other.radius).abs() < 0.001
+			&& shortest_angle(self.yaw, other.yaw).abs() < 0.0001
+			&& (self.pitch - other.pitch).abs() < 0.0001
+	}
+}
+
+/// The angle to turn by to get from `from` to `to` the short way around.
+fn shortest_angle(from: f32, to: f32) -> f32 {
+	(to - from + PI).rem_euclid(TAU) - PI
+}
 
+/// How a [`PanOrbitCamera`] looks at its focus point.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CameraMode {
+	/// Perspective, orbiting freely around the focus point.
+	#[default]
+	Orbit,
+	/// Orthographic, looking straight down. Zooming scales the view.
+	TopDown,
+	/// Perspective at a fixed pitch, scrolled by moving the cursor to the edges of the window.
+	Rts,
+}
+
+impl CameraMode {
+	/// The mode after this one, going around in a loop.
+	pub fn next(self) -> Self {
+		match self {
+			CameraMode::Orbit => CameraMode::TopDown,
+			CameraMode::TopDown => CameraMode::Rts,
+			CameraMode::Rts => CameraMode::Orbit,
+		}
+	}
+}
+
+/// Tags an entity as capable of panning and orbiting.
 #[derive(Component)]
 pub struct PanOrbitCamera {
 	/// The "focus point" to orbit around. It is automatically updated when panning the camera
 	pub focus: Vec3,
 	pub radius: f32,
-	pub upside_down: bool,
+	pub yaw: f32,
+	pub pitch: f32,
+	/// Direction that the camera orbits around. The camera is level with the focus point when the pitch is zero.
+	pub up: Vec3,
+	pub min_radius: f32,
+	pub max_radius: f32,
+	/// Pitch is kept between these, so that the camera can't flip over or go below the ground.
+	pub min_pitch: f32,
+	pub max_pitch: f32,
+	/// How quickly the camera moves to where it is going when it isn't moved directly. Higher is faster.
+	pub smoothing: f32,
+	/// The view that [`reset`](Self::reset) goes back to.
+	pub home: CameraView,
+	pub mode: CameraMode,
+	/// Pitch of the camera in [`CameraMode::Rts`].
+	pub rts_pitch: f32,
+	/// The view that the camera is moving towards.
+	transition: Option<CameraView>,
+	following: Option<Entity>,
+	/// The perspective projection, put aside while the camera is orthographic.
+	perspective: Option<PerspectiveProjection>,
 }
 
 impl Default for PanOrbitCamera {
 	fn default() -> Self {
+		let view = CameraView::default();
 		PanOrbitCamera {
-			focus: Vec3::ZERO,
-			radius: 5.0,
-			upside_down: false,
+			focus: view.focus,
+			radius: view.radius,
+			yaw: view.yaw,
+			pitch: view.pitch,
+			up: Vec3::Y,
+			min_radius: 0.05,
+			max_radius: f32::INFINITY,
+			min_pitch: -FRAC_PI_2,
+			max_pitch: FRAC_PI_2,
+			smoothing: 5.,
+			home: view,
+			mode: CameraMode::Orbit,
+			rts_pitch: -0.3 * PI,
+			transition: None,
+			following: None,
+			perspective: None,
 		}
 	}
 }
 
-/// Pan the camera with middle mouse click, zoom with scroll wheel, orbit with right mouse click.
-pub fn pan_orbit_camera(
-	windows: Res<Windows>,
-	mut ev_motion: EventReader<MouseMotion>,
-	mut ev_scroll: EventReader<MouseWheel>,
-	input_mouse: Res<Input<MouseButton>>,
-	mut query: Query<(&mut PanOrbitCamera, &mut Transform, &PerspectiveProjection)>,
+impl PanOrbitCamera {
+	/// A camera looking from `view`, that also resets to it.
+	pub fn from_view(view: CameraView) -> Self {
+		let mut camera = Self {
+			home: view,
+			..Default::default()
+		};
+		camera.set_view(view);
+		camera
+	}
+
+	pub fn with_up(mut self, up: Vec3) -> Self {
+		self.up = up;
+		self
+	}
+
+	pub fn with_radius_limits(mut self, min: f32, max: f32) -> Self {
+		self.min_radius = min;
+		self.max_radius = max;
+		self.set_view(self.view());
+		self
+	}
+
+	pub fn with_mode(mut self, mode: CameraMode) -> Self {
+		self.mode = mode;
+		self
+	}
+
+	pub fn with_pitch_limits(mut self, min: f32, max: f32) -> Self {
+		self.min_pitch = min;
+		self.max_pitch = max;
+		self.set_view(self.view());
+		self
+	}
+
+	pub fn view(&self) -> CameraView {
+		CameraView {
+			focus: self.focus,
+			radius: self.radius,
+			yaw: self.yaw,
+			pitch: self.pitch,
+		}
+	}
+
+	/// Jump straight to `view`, within the limits.
+	pub fn set_view(&mut self, view: CameraView) {
+		let view = self.clamp(view);
+		self.focus = view.focus;
+		self.radius = view.radius;
+		self.yaw = view.yaw;
+		self.pitch = view.pitch;
+	}
+
+	/// `view` moved within the limits on radius and pitch.
+	pub fn clamp(&self, view: CameraView) -> CameraView {
+		CameraView {
+			radius: view.radius.clamp(self.min_radius, self.max_radius),
+			pitch: view.pitch.clamp(self.min_pitch, self.max_pitch),
+			..view
+		}
+	}
+
+	/// Move smoothly to `view`.
+	pub fn animate_to(&mut self, view: CameraView) {
+		self.transition = Some(self.clamp(view));
+	}
+
+	/// Move smoothly to look at `point`, without changing the angle or distance.
+	pub fn focus_on(&mut self, point: Vec3) {
+		let view = self.transition.unwrap_or_else(|| self.view());
+		self.animate_to(CameraView { focus: point, ..view });
+	}
+
+	/// Keep the focus point on `entity` as it moves, until it is gone or the camera is panned.
+	pub fn follow(&mut self, entity: Entity) {
+		self.following = Some(entity);
+	}
+
+	pub fn stop_following(&mut self) {
+		self.following = None;
+	}
+
+	pub fn following(&self) -> Option<Entity> {
+		self.following
+	}
+
+	/// Move smoothly back to the [`home`](Self::home) view, and stop following.
+	pub fn reset(&mut self) {
+		self.following = None;
+		self.animate_to(self.home);
+	}
+
+	pub fn is_animating(&self) -> bool {
+		self.transition.is_some()
+	}
+
+	/// Move towards the view being animated to, for `delta_time` seconds.
+	pub fn step(&mut self, delta_time: f32) {
+		let target = match self.transition {
+			Some(target) => target,
+			None => return,
+		};
+		// exponential smoothing, so that it slows down the closer it gets, no matter the frame rate
+		let t = 1. - (-self.smoothing * delta_time).exp();
+		let view = self.view().lerp(&target, t);
+		if view.is_close_to(&target) {
+			self.set_view(target);
+			self.transition = None;
+		} else {
+			self.set_view(view);
+		}
+	}
+
+	/// Rotation that turns Y to point up.
+	fn up_rotation(&self) -> Quat {
+		Quat::from_rotation_arc(Vec3::Y, self.up.normalize())
+	}
+
+	/// Directions to the right of the camera and straight ahead of it, level with the ground.
+	pub fn ground_axes(&self) -> (Vec3, Vec3) {
+		let heading = self.up_rotation() * Quat::from_rotation_y(self.yaw);
+		(heading * Vec3::X, heading * -Vec3::Z)
+	}
+
+	/// The pitch that the camera is looking at the focus point with, which only [`CameraMode::Orbit`] lets change.
+	pub fn effective_pitch(&self) -> f32 {
+		match self.mode {
+			CameraMode::Orbit => self.pitch,
+			CameraMode::TopDown => -FRAC_PI_2,
+			CameraMode::Rts => self.rts_pitch,
+		}
+	}
+
+	/// Half the height of the view in [`CameraMode::TopDown`], as high as a perspective camera with a field of view
+	/// of `fov` would see at the focus point.
+	fn top_down_scale(&self, fov: f32) -> f32 {
+		self.radius * (fov / 2.).tan()
+	}
+
+	/// Where the camera is and which way it faces.
+	pub fn transform(&self) -> Transform {
+		// orbit around Y, then turn Y to point up
+		let rotation = self.up_rotation() * Quat::from_euler(EulerRot::YXZ, self.yaw, self.effective_pitch(), 0.);
+		let distance = match self.mode {
+			CameraMode::TopDown => TOP_DOWN_DISTANCE,
+			CameraMode::Orbit | CameraMode::Rts => self.radius,
+		};
+		Transform {
+			translation: self.focus + rotation * Vec3::new(0.0, 0.0, distance),
+			rotation,
+			..Default::default()
+		}
+	}
+}
+
+/// Tells every [`PanOrbitCamera`] where to look. Send a [`CameraCommandFor`] to tell just one of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraCommand {
+	/// Move smoothly to look at a point.
+	FocusOn(Vec3),
+	/// Move smoothly to look at an entity where it is now.
+	Focus(Entity),
+	/// Keep looking at an entity as it moves.
+	Follow(Entity),
+	StopFollowing,
+	/// Move smoothly to a view.
+	AnimateTo(CameraView),
+	/// Move smoothly back to each camera's home view.
+	Reset,
+	SetMode(CameraMode),
+	/// Change each camera's home view, without moving. Follow it with a [`Reset`](Self::Reset) to go there.
+	SetHome(CameraView),
+}
+
+/// While this is true, the keyboard doesn't move any camera, so that something else can use the same keys.
+#[derive(Debug, Default)]
+pub struct CameraKeysBlocked(pub bool);
+
+/// A [`CameraCommand`] for only one camera, so that several cameras can each look at something different.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraCommandFor {
+	pub camera: Entity,
+	pub command: CameraCommand,
+}
+
+/// Adds [`pan_orbit_camera`], and moves every [`PanOrbitCamera`] as told by [`CameraCommand`]s.
+///
+/// Works without a window or mouse, like in headless tests, in which case cameras can still be moved by commands
+/// and the keyboard.
+pub struct PanOrbitCameraPlugin;
+
+/// Labels for ordering systems around the camera.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub enum CameraSystem {
+	Input,
+	/// Cameras' transforms are up to date after this.
+	Update,
+}
+
+impl Plugin for PanOrbitCameraPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_plugin(InputActionPlugin)
+			.add_event::<CameraCommand>()
+			.init_resource::<CameraKeysBlocked>()
+			.add_event::<CameraCommandFor>()
+			.add_system(handle_camera_commands.before(CameraSystem::Input))
+			.add_system(pan_orbit_camera.label(CameraSystem::Input))
+			.add_system(update_cameras.label(CameraSystem::Update).after(CameraSystem::Input))
+			.add_system(update_projections.after(CameraSystem::Update));
+	}
+}
+
+pub fn handle_camera_commands(
+	mut commands: EventReader<CameraCommand>,
+	mut commands_for: EventReader<CameraCommandFor>,
+	targets: Query<&GlobalTransform>,
+	mut cameras: Query<(Entity, &mut PanOrbitCamera)>,
 ) {
-	// change input mapping for orbit and panning here
-	let orbit_button = MouseButton::Right;
-	let pan_button = MouseButton::Middle;
+	let commands = commands.iter().map(|command| (None, *command))
+		.chain(commands_for.iter().map(|command| (Some(command.camera), command.command)));
+	for (only, command) in commands {
+		for (entity, mut camera) in cameras.iter_mut() {
+			if only.is_some_and(|only| only != entity) {
+				continue;
+			}
+			match command {
+				CameraCommand::FocusOn(point) => camera.focus_on(point),
+				CameraCommand::Focus(entity) => {
+					if let Ok(target) = targets.get(entity) {
+						camera.stop_following();
+						camera.focus_on(target.translation);
+					}
+				}
+				CameraCommand::Follow(entity) => camera.follow(entity),
+				CameraCommand::StopFollowing => camera.stop_following(),
+				CameraCommand::AnimateTo(view) => camera.animate_to(view),
+				CameraCommand::Reset => camera.reset(),
+				CameraCommand::SetMode(mode) => camera.mode = mode,
+				CameraCommand::SetHome(view) => camera.home = view,
+			}
+		}
+	}
+}
+
+/// What the fingers on the screen do this frame, in pixels: one finger orbits, and two fingers pan and pinch to zoom.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TouchGesture {
+	orbit: Vec2,
+	pan: Vec2,
+	/// What to multiply the distance to the focus point by.
+	zoom: f32,
+}
 
+impl TouchGesture {
+	const NONE: TouchGesture = TouchGesture {
+		orbit: Vec2::ZERO,
+		pan: Vec2::ZERO,
+		zoom: 1.,
+	};
+
+	/// The gesture made by touches that moved from the first to the second position of each pair.
+	/// Anything other than one or two touches doesn't do anything.
+	fn from_touches(touches: &[(Vec2, Vec2)]) -> Self {
+		match *touches {
+			[(previous, current)] => TouchGesture {
+				orbit: current - previous,
+				..Self::NONE
+			},
+			[(previous_a, current_a), (previous_b, current_b)] => {
+				let previous_spread = previous_a.distance(previous_b);
+				let spread = current_a.distance(current_b);
+				TouchGesture {
+					pan: (current_a + current_b - previous_a - previous_b) / 2.,
+					// spreading the fingers apart zooms in
+					zoom: if spread > f32::EPSILON && previous_spread > f32::EPSILON { previous_spread / spread } else { 1. },
+					..Self::NONE
+				}
+			}
+			_ => Self::NONE,
+		}
+	}
+}
+
+/// Orbit, pan and zoom with the [`Action`]s for them. By default, drag with the right mouse button to orbit,
+/// drag with the middle mouse button to pan and scroll to zoom, or use Q / E, WASD and + / - on the keyboard.
+/// On touch screens, drag with one finger to orbit, drag with two to pan, and pinch to zoom.
+/// Panning stops the camera from following anything, and any of them stop it from animating.
+///
+/// [`Action::SwitchCameraMode`], C by default, goes through the [`CameraMode`]s. Only [`CameraMode::Orbit`] can change pitch,
+/// and [`CameraMode::Rts`] also scrolls when the cursor is at the edge of the window.
+///
+/// Every camera only listens to the mouse while the cursor is in its window, and to the keyboard and touches while its
+/// window is focused. Cameras without a window, like ones that render to an image, only listen to the keyboard.
+/// None of them listen to the keyboard while [`CameraKeysBlocked`] is set.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn pan_orbit_camera(
+	time: Res<Time>,
+	windows: Option<Res<Windows>>,
+	actions: Res<ActionState>,
+	keys_blocked: Res<CameraKeysBlocked>,
+	touches: Option<Res<Touches>>,
+	motion_events: Option<Res<Events<MouseMotion>>>,
+	mut motion_reader: Local<ManualEventReader<MouseMotion>>,
+	mut query: Query<(&mut PanOrbitCamera, &Camera, &Transform, Option<&PerspectiveProjection>, Option<&OrthographicProjection>)>,
+) {
+	// read the motion every frame, even when it isn't used, so that motion from before a button was pressed
+	// doesn't jerk the camera once it is
+	let motion: Vec2 = motion_events
+		.map(|events| motion_reader.iter(&events).fold(Vec2::ZERO, |sum, event| sum + event.delta))
+		.unwrap_or(Vec2::ZERO);
 	let mut pan = Vec2::ZERO;
 	let mut rotation_move = Vec2::ZERO;
-	let mut scroll = 0.0;
-	let mut orbit_button_changed = false;
+	if actions.pressed(Action::Orbit) {
+		rotation_move = motion;
+	} else if actions.pressed(Action::Pan) {
+		// Pan only if we're not rotating at the moment
+		pan = motion;
+	}
+	let touches: Vec<(Vec2, Vec2)> = touches
+		.map(|touches| touches.iter().map(|touch| (touch.previous_position(), touch.position())).collect())
+		.unwrap_or_default();
+	let gesture = TouchGesture::from_touches(&touches);
 
-	if input_mouse.pressed(orbit_button) {
-		for ev in ev_motion.iter() {
-			rotation_move += ev.delta;
+	let scroll = actions.amount(Action::Zoom);
+	let delta = time.delta_seconds();
+	let key_orbit = (actions.amount(Action::OrbitRight) - actions.amount(Action::OrbitLeft)) * KEY_ORBIT_SPEED * delta;
+	let key_pan = Vec2::new(
+		actions.amount(Action::PanRight) - actions.amount(Action::PanLeft),
+		actions.amount(Action::PanForward) - actions.amount(Action::PanBack),
+	) * KEY_PAN_SPEED * delta;
+	let key_zoom = (actions.amount(Action::ZoomIn) - actions.amount(Action::ZoomOut)) * KEY_ZOOM_SPEED * delta;
+	let switch_mode = actions.just_pressed(Action::SwitchCameraMode);
+
+	for (mut pan_orbit, camera, transform, perspective, orthographic) in query.iter_mut() {
+		let window = match camera.target {
+			RenderTarget::Window(id) => windows.as_ref().and_then(|windows| windows.get(id)),
+			RenderTarget::Image(_) => None,
+		};
+		let focused = window.is_none_or(Window::is_focused);
+		let keyboard = focused && !keys_blocked.0;
+		let cursor = window.and_then(|window| window.cursor_position());
+		let window_size = window.map(|window| Vec2::new(window.width(), window.height()));
+
+		if switch_mode && focused {
+			pan_orbit.mode = pan_orbit.mode.next();
+			info!("Camera mode: {:?}", pan_orbit.mode);
 		}
-	} else if input_mouse.pressed(pan_button) {
-		// Pan only if we're not rotating at the moment
-		for ev in ev_motion.iter() {
-			pan += ev.delta;
+		let mut view = pan_orbit.view();
+		if keyboard {
+			view.radius *= (-key_zoom).exp();
+			view.yaw += key_orbit;
+		}
+
+		// everything after this is measured in pixels, so it needs a window
+		let mut drag = Vec2::ZERO;
+		if let Some(window_size) = window_size {
+			let mut orbit = Vec2::ZERO;
+			if cursor.is_some() {
+				orbit += rotation_move;
+				drag += pan;
+				view.radius -= scroll * view.radius * 0.2;
+			}
+			if focused {
+				orbit += gesture.orbit;
+				drag += gesture.pan;
+				view.radius *= gesture.zoom;
+			}
+			view.yaw -= orbit.x / window_size.x * std::f32::consts::PI * 2.0;
+			if pan_orbit.mode == CameraMode::Orbit {
+				view.pitch -= orbit.y / window_size.y * std::f32::consts::PI;
+			}
+			// make panning distance independent of resolution and FOV,
+			drag = match (perspective, orthographic) {
+				// make panning proportional to distance away from focus point
+				(Some(projection), _) => drag * Vec2::new(projection.fov * projection.aspect_ratio, projection.fov) / window_size * pan_orbit.radius,
+				// the whole height of the window is twice the scale
+				(None, Some(projection)) => drag * 2. * projection.scale / window_size.y,
+				(None, None) => Vec2::ZERO,
+			};
+		}
+		// translate by local axes
+		let right = transform.rotation * Vec3::X * -drag.x;
+		let up = transform.rotation * Vec3::Y * drag.y;
+		view.focus += right + up;
+
+		let mut key_pan = if keyboard { key_pan } else { Vec2::ZERO };
+		if let (CameraMode::Rts, Some(cursor), Some(window_size)) = (pan_orbit.mode, cursor, window_size) {
+			key_pan += edge_scroll_direction(cursor, window_size) * KEY_PAN_SPEED * delta;
+		}
+		if key_pan != Vec2::ZERO {
+			// along the ground, whichever way the camera is pitched
+			let (right, forward) = pan_orbit.ground_axes();
+			view.focus += (right * key_pan.x + forward * key_pan.y) * view.radius;
+		}
+
+		if view == pan_orbit.view() {
+			continue;
+		}
+		if view.focus != pan_orbit.focus {
+			pan_orbit.stop_following();
 		}
+		pan_orbit.transition = None;
+		pan_orbit.set_view(view);
 	}
-	for ev in ev_scroll.iter() {
-		scroll += ev.y;
+}
+
+/// Which way to scroll when the cursor is near the edges of a window of `window_size`: -1, 0 or 1 along each axis,
+/// up being positive.
+fn edge_scroll_direction(cursor: Vec2, window_size: Vec2) -> Vec2 {
+	let axis = |position: f32, size: f32| {
+		if position < EDGE_SCROLL_MARGIN {
+			-1.
+		} else if position > size - EDGE_SCROLL_MARGIN {
+			1.
+		} else {
+			0.
+		}
+	};
+	// the cursor's origin is the bottom left corner
+	Vec2::new(axis(cursor.x, window_size.x), axis(cursor.y, window_size.y))
+}
+
+/// Follow, animate, and move every camera's transform to match its view.
+pub fn update_cameras(
+	time: Res<Time>,
+	targets: Query<&GlobalTransform, Without<PanOrbitCamera>>,
+	mut cameras: Query<(&mut PanOrbitCamera, &mut Transform)>,
+) {
+	for (mut camera, mut transform) in cameras.iter_mut() {
+		if let Some(entity) = camera.following {
+			match targets.get(entity) {
+				Ok(target) => camera.focus_on(target.translation),
+				Err(_) => camera.stop_following(),
+			}
+		}
+		camera.step(time.delta_seconds());
+		let new_transform = camera.transform();
+		if transform.translation != new_transform.translation || transform.rotation != new_transform.rotation {
+			transform.translation = new_transform.translation;
+			transform.rotation = new_transform.rotation;
+		}
 	}
-	if input_mouse.just_released(orbit_button) || input_mouse.just_pressed(orbit_button) {
-		orbit_button_changed = true;
+}
+
+/// Give cameras in [`CameraMode::TopDown`] an orthographic projection, and the others a perspective one.
+#[allow(clippy::type_complexity)]
+pub fn update_projections(
+	mut commands: Commands,
+	mut cameras: Query<(
+		Entity,
+		&mut PanOrbitCamera,
+		&mut Camera,
+		Option<&PerspectiveProjection>,
+		Option<&mut OrthographicProjection>,
+	)>,
+) {
+	for (entity, mut pan_orbit, mut camera, perspective, orthographic) in cameras.iter_mut() {
+		let top_down = pan_orbit.mode == CameraMode::TopDown;
+		match (top_down, perspective, orthographic) {
+			(true, Some(perspective), _) => {
+				let projection = OrthographicProjection {
+					scaling_mode: ScalingMode::FixedVertical,
+					depth_calculation: DepthCalculation::Distance,
+					scale: pan_orbit.top_down_scale(perspective.fov),
+					..Default::default()
+				};
+				camera.near = projection.near;
+				camera.far = projection.far;
+				pan_orbit.perspective = Some(perspective.clone());
+				commands.entity(entity)
+					.remove::<PerspectiveProjection>()
+					.insert(projection);
+			}
+			(true, None, Some(mut orthographic)) => {
+				let fov = pan_orbit.perspective.as_ref().map_or(PerspectiveProjection::default().fov, |perspective| perspective.fov);
+				let scale = pan_orbit.top_down_scale(fov);
+				if orthographic.scale != scale {
+					orthographic.scale = scale;
+				}
+			}
+			(false, None, Some(_)) => {
+				let projection = pan_orbit.perspective.take().unwrap_or_default();
+				camera.near = projection.near;
+				camera.far = projection.far;
+				commands.entity(entity)
+					.remove::<OrthographicProjection>()
+					.insert(projection);
+			}
+			_ => {}
+		}
 	}
+}
 
-	for (mut pan_orbit, mut transform, projection) in query.iter_mut() {
-		if orbit_button_changed {
-			// only check for upside down when orbiting started or ended this frame
-			// if the camera is "upside" down, panning horizontally would be inverted, so invert the input to make it correct
-			let up = transform.rotation * Vec3::Y;
-			pan_orbit.upside_down = up.y <= 0.0;
+/// A ray cast from the camera into the world.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+	pub origin: Vec3,
+	/// Unit vector pointing in the direction of the ray.
+	pub direction: Vec3,
+}
+
+impl Ray {
+	/// Point where the ray crosses the plane, if it does.
+	pub fn intersect_plane(&self, plane_origin: Vec3, plane_normal: Vec3) -> Option<Vec3> {
+		let denominator = self.direction.dot(plane_normal);
+		if denominator.abs() < f32::EPSILON {
+			return None;
+		}
+		let t = (plane_origin - self.origin).dot(plane_normal) / denominator;
+		if t < 0. {
+			return None;
 		}
+		Some(self.origin + self.direction * t)
+	}
 
-		let mut any = false;
-		if rotation_move.length_squared() > 0.0 {
-			any = true;
-			let window = get_primary_window_size(&windows);
-			let delta_x = {
-				let delta = rotation_move.x / window.x * std::f32::consts::PI * 2.0;
-				if pan_orbit.upside_down { -delta } else { delta }
+	/// How far along the ray the closest point to `point` is.
+	pub fn closest_distance_along(&self, point: Vec3) -> f32 {
+		(point - self.origin).dot(self.direction)
+	}
+
+	/// Shortest distance between `point` and the ray.
+	pub fn distance_to_point(&self, point: Vec3) -> f32 {
+		let t = self.closest_distance_along(point).max(0.);
+		point.distance(self.origin + self.direction * t)
+	}
+}
+
+/// Ray from the camera through the mouse cursor, if the cursor is in the camera's window.
+pub fn cursor_ray(windows: &Windows, camera: &Camera, camera_transform: &GlobalTransform) -> Option<Ray> {
+	let window = match camera.target {
+		RenderTarget::Window(id) => windows.get(id)?,
+		RenderTarget::Image(_) => return None,
+	};
+	let cursor = window.cursor_position()?;
+	let window_size = Vec2::new(window.width(), window.height());
+	let ndc = cursor / window_size * 2. - Vec2::ONE;
+	Some(ndc_ray(ndc, camera.projection_matrix, camera_transform))
+}
+
+/// Ray from the camera through a point on the screen, in normalized device coordinates from -1 at the bottom left
+/// to 1 at the top right. Works with perspective and orthographic projections alike.
+pub fn ndc_ray(ndc: Vec2, projection_matrix: Mat4, camera_transform: &GlobalTransform) -> Ray {
+	let ndc_to_world = camera_transform.compute_matrix() * projection_matrix.inverse();
+	// depth is reversed, so 1 is the near plane. The far plane can be at infinity, so pick something in between instead.
+	let near = ndc_to_world.project_point3(ndc.extend(1.));
+	let far = ndc_to_world.project_point3(ndc.extend(0.5));
+	Ray {
+		origin: near,
+		direction: (far - near).normalize(),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use bevy::render::camera::CameraProjection;
+
+	use super::*;
+
+	#[test]
+	fn test_ray_intersect_plane() {
+		let ray = Ray {
+			origin: Vec3::new(0., 0., 10.),
+			direction: -Vec3::Z,
+		};
+		assert_eq!(ray.intersect_plane(Vec3::ZERO, Vec3::Z), Some(Vec3::ZERO));
+		assert_eq!(ray.intersect_plane(Vec3::new(0., 0., 20.), Vec3::Z), None);
+		assert_eq!(ray.intersect_plane(Vec3::ZERO, Vec3::X), None);
+	}
+
+	#[test]
+	fn test_ray_distance_to_point() {
+		let ray = Ray {
+			origin: Vec3::ZERO,
+			direction: Vec3::X,
+		};
+		assert_eq!(ray.distance_to_point(Vec3::new(5., 2., 0.)), 2.);
+		assert_eq!(ray.distance_to_point(Vec3::new(-3., 4., 0.)), 5.);
+		assert_eq!(ray.closest_distance_along(Vec3::new(5., 2., 0.)), 5.);
+	}
+
+	#[test]
+	fn test_clamp_view() {
+		let camera = PanOrbitCamera {
+			min_radius: 2.,
+			max_radius: 10.,
+			min_pitch: -1.,
+			max_pitch: -0.1,
+			..Default::default()
+		};
+		let view = camera.clamp(CameraView { radius: 50., pitch: 1., ..Default::default() });
+		assert_eq!((view.radius, view.pitch), (10., -0.1));
+		let view = camera.clamp(CameraView { radius: 0., pitch: -3., ..Default::default() });
+		assert_eq!((view.radius, view.pitch), (2., -1.));
+	}
+
+	#[test]
+	fn test_top_down_view() {
+		let camera = PanOrbitCamera {
+			up: Vec3::Z,
+			..PanOrbitCamera::from_view(CameraView { radius: 20., pitch: -FRAC_PI_2, ..Default::default() })
+		};
+		let transform = camera.transform();
+		// straight above the focus point, with the world's axes lined up with the screen's
+		assert!(transform.translation.abs_diff_eq(Vec3::new(0., 0., 20.), 0.0001));
+		assert!(transform.rotation.abs_diff_eq(Quat::IDENTITY, 0.0001));
+	}
+
+	#[test]
+	fn test_animate_to_view() {
+		let mut camera = PanOrbitCamera::default();
+		// a quarter turn is shorter the other way around
+		let target = CameraView { focus: Vec3::X, radius: 10., yaw: -FRAC_PI_2 + TAU, pitch: -0.5 };
+		camera.animate_to(target);
+		camera.step(0.1);
+		assert!(camera.yaw < 0. && camera.focus.x > 0. && camera.focus.x < 1.);
+		for _ in 0..100 {
+			camera.step(0.1);
+		}
+		assert!(!camera.is_animating());
+		assert_eq!(camera.view(), target);
+
+		camera.reset();
+		for _ in 0..100 {
+			camera.step(0.1);
+		}
+		assert_eq!(camera.view(), camera.home);
+	}
+
+	#[test]
+	fn test_ground_axes() {
+		let camera = PanOrbitCamera::default().with_up(Vec3::Z).with_pitch_limits(-FRAC_PI_2, 0.);
+		let (right, forward) = camera.ground_axes();
+		assert!(right.abs_diff_eq(Vec3::X, 0.0001) && forward.abs_diff_eq(Vec3::Y, 0.0001));
+		let camera = PanOrbitCamera {
+			yaw: FRAC_PI_2,
+			..camera
+		};
+		let (right, forward) = camera.ground_axes();
+		assert!(right.abs_diff_eq(Vec3::Y, 0.0001) && forward.abs_diff_eq(-Vec3::X, 0.0001));
+	}
+
+	#[test]
+	fn test_picking_in_every_mode() {
+		let mut camera = PanOrbitCamera::from_view(CameraView { radius: 20., pitch: -0.5, ..Default::default() })
+			.with_up(Vec3::Z)
+			.with_pitch_limits(-FRAC_PI_2, -0.1);
+		let mut perspective = PerspectiveProjection::default();
+		perspective.update(800., 600.);
+		let mut orthographic = OrthographicProjection {
+			scaling_mode: ScalingMode::FixedVertical,
+			scale: camera.top_down_scale(perspective.fov),
+			..Default::default()
+		};
+		orthographic.update(800., 600.);
+
+		for mode in [CameraMode::Orbit, CameraMode::TopDown, CameraMode::Rts] {
+			camera.mode = mode;
+			let projection = match mode {
+				CameraMode::TopDown => orthographic.get_projection_matrix(),
+				_ => perspective.get_projection_matrix(),
 			};
-			let delta_y = rotation_move.y / window.y * std::f32::consts::PI;
-			let yaw = Quat::from_rotation_y(-delta_x);
-			let pitch = Quat::from_rotation_x(-delta_y);
-			transform.rotation = yaw * transform.rotation; // rotate around global y axis
-			transform.rotation = transform.rotation * pitch; // rotate around local x axis
-		} else if pan.length_squared() > 0.0 {
-			any = true;
-			// make panning distance independent of resolution and FOV,
-			let window = get_primary_window_size(&windows);
-			pan *= Vec2::new(projection.fov * projection.aspect_ratio, projection.fov) / window;
-			// translate by local axes
-			let right = transform.rotation * Vec3::X * -pan.x;
-			let up = transform.rotation * Vec3::Y * pan.y;
-			// make panning proportional to distance away from focus point
-			let translation = (right + up) * pan_orbit.radius;
-			pan_orbit.focus += translation;
-		} else if scroll.abs() > 0.0 {
-			any = true;
-			pan_orbit.radius -= scroll * pan_orbit.radius * 0.2;
-			// dont allow zoom to reach zero or you get stuck
-			pan_orbit.radius = f32::max(pan_orbit.radius, 0.05);
-		}
-
-		if any {
-			// emulating parent/child to make the yaw/y-axis rotation behave like a turntable
-			// parent = x and y rotation
-			// child = z-offset
-			let rot_matrix = Mat3::from_quat(transform.rotation);
-			transform.translation = pan_orbit.focus + rot_matrix.mul_vec3(Vec3::new(0.0, 0.0, pan_orbit.radius));
-		}
-	}
-}
-
-fn get_primary_window_size(windows: &Res<Windows>) -> Vec2 {
-	let window = windows.get_primary().unwrap();
-	let window = Vec2::new(window.width() as f32, window.height() as f32);
-	window
+			let transform = GlobalTransform::from(camera.transform());
+			// the middle of the screen is the focus point
+			let ray = ndc_ray(Vec2::ZERO, projection, &transform);
+			let hit = ray.intersect_plane(Vec3::ZERO, Vec3::Z).unwrap();
+			assert!(hit.abs_diff_eq(camera.focus, 0.001), "{:?}: {}", mode, hit);
+			// and the right edge is to the right of it
+			let ray = ndc_ray(Vec2::new(1., 0.), projection, &transform);
+			let hit = ray.intersect_plane(Vec3::ZERO, Vec3::Z).unwrap();
+			assert!(hit.x > 1., "{:?}: {}", mode, hit);
+		}
+		// the top down view is as wide as the perspective one at the focus point
+		camera.mode = CameraMode::TopDown;
+		let ray = ndc_ray(Vec2::new(0., 1.), orthographic.get_projection_matrix(), &GlobalTransform::from(camera.transform()));
+		let hit = ray.intersect_plane(Vec3::ZERO, Vec3::Z).unwrap();
+		assert!((hit.y - 20. * (perspective.fov / 2.).tan()).abs() < 0.001, "{}", hit);
+	}
+
+	#[test]
+	fn test_touch_gestures() {
+		let one = TouchGesture::from_touches(&[(Vec2::ZERO, Vec2::new(3., 4.))]);
+		assert_eq!(one, TouchGesture { orbit: Vec2::new(3., 4.), ..TouchGesture::NONE });
+		// one finger stays put and the other moves away, so the middle moves right and they are twice as far apart
+		let two = TouchGesture::from_touches(&[
+			(Vec2::new(0., 0.), Vec2::new(0., 0.)),
+			(Vec2::new(10., 0.), Vec2::new(20., 0.)),
+		]);
+		assert_eq!(two, TouchGesture { pan: Vec2::new(5., 0.), zoom: 0.5, ..TouchGesture::NONE });
+		assert_eq!(TouchGesture::from_touches(&[]), TouchGesture::NONE);
+		assert_eq!(TouchGesture::from_touches(&[(Vec2::ZERO, Vec2::ONE); 3]), TouchGesture::NONE);
+	}
+
+	#[test]
+	fn test_edge_scroll_direction() {
+		let size = Vec2::new(800., 600.);
+		assert_eq!(edge_scroll_direction(Vec2::new(400., 300.), size), Vec2::ZERO);
+		assert_eq!(edge_scroll_direction(Vec2::new(2., 599.), size), Vec2::new(-1., 1.));
+	}
+
+	#[test]
+	fn test_without_window() {
+		let mut time = Time::default();
+		time.update();
+		std::thread::sleep(std::time::Duration::from_millis(16));
+		time.update();
+		let mut app = App::new();
+		app.insert_resource(time)
+			.add_plugin(PanOrbitCameraPlugin);
+		let mut spawn_camera = || app.world.spawn()
+			.insert_bundle(PerspectiveCameraBundle::new_3d())
+			.insert(PanOrbitCamera::default())
+			.id();
+		let (first, second) = (spawn_camera(), spawn_camera());
+		// no window, mouse or keyboard, and nothing breaks
+		app.update();
+
+		app.world.resource_mut::<Events<CameraCommandFor>>().send(CameraCommandFor {
+			camera: second,
+			command: CameraCommand::SetMode(CameraMode::TopDown),
+		});
+		let mut keys = Input::<KeyCode>::default();
+		keys.press(KeyCode::E);
+		app.insert_resource(keys);
+		app.update();
+		app.update();
+
+		// the keyboard still works, and moves every camera that isn't in a window
+		for camera in [first, second] {
+			assert!(app.world.get::<PanOrbitCamera>(camera).unwrap().yaw > 0.);
+		}
+		// each camera has its own projection
+		assert!(app.world.get::<PerspectiveProjection>(first).is_some());
+		assert!(app.world.get::<PerspectiveProjection>(second).is_none());
+		assert!(app.world.get::<OrthographicProjection>(second).is_some());
+	}
+
+	#[test]
+	fn test_set_home() {
+		let mut world = World::new();
+		world.insert_resource(Events::<CameraCommand>::default());
+		world.insert_resource(Events::<CameraCommandFor>::default());
+		let camera = world.spawn().insert(PanOrbitCamera::default()).id();
+		let mut stage = SystemStage::single_threaded().with_system(handle_camera_commands);
+		let home = CameraView { focus: Vec3::new(1., 2., 3.), ..PanOrbitCamera::default().view() };
+
+		world.resource_mut::<Events<CameraCommand>>().send(CameraCommand::SetHome(home));
+		stage.run(&mut world);
+		// stays where the player left it
+		let orbit = world.get::<PanOrbitCamera>(camera).unwrap();
+		assert_eq!(orbit.home, home);
+		assert!(!orbit.is_animating());
+
+		world.resource_mut::<Events<CameraCommand>>().send(CameraCommand::Reset);
+		stage.run(&mut world);
+		assert!(world.get::<PanOrbitCamera>(camera).unwrap().is_animating());
+	}
+
+	#[test]
+	fn test_shortest_angle() {
+		assert!((shortest_angle(0.1, TAU - 0.1) + 0.2).abs() < 0.0001);
+		assert!((shortest_angle(-3., 3.) - (6. - TAU)).abs() < 0.0001);
+	}
 }
@@ -0,0 +1,308 @@
+//! Controls that can be rebound. Systems ask whether an [`Action`] is happening instead of reading keys and buttons,
+//! and the [`InputMap`] says which keys and buttons do what.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use bevy::{prelude::*, ecs::event::{Events, ManualEventReader}, input::{mouse::MouseWheel, InputSystem}};
+use serde::{Deserialize, Serialize};
+
+/// Something the player can do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+	/// Orbit the camera by dragging the mouse while this is held.
+	Orbit,
+	/// Pan the camera by dragging the mouse while this is held.
+	Pan,
+	/// Zoom the camera, by how far the mouse wheel is scrolled.
+	Zoom,
+	OrbitLeft,
+	OrbitRight,
+	PanLeft,
+	PanRight,
+	PanForward,
+	PanBack,
+	ZoomIn,
+	ZoomOut,
+	/// Go to the next [`CameraMode`](crate::camera::CameraMode).
+	SwitchCameraMode,
+	/// Move the camera to look at the tower or enemy under the cursor.
+	FocusCamera,
+	/// Keep the camera on the enemy under the cursor, or stop following.
+	FollowWithCamera,
+	/// Move the camera back to the level's view.
+	ResetCamera,
+	ToggleHealthBars,
+	ToggleDamageNumbers,
+	StartWave,
+	/// Stop or resume the game.
+	Pause,
+	PlaceTower,
+	ToggleEditor,
+	/// Write the level that is being edited back to its file.
+	SaveLevel,
+	/// Remove the path node that is selected in the editor.
+	DeleteNode,
+	ToggleInspector,
+	/// Select the next tower in the inspector.
+	InspectNextTower,
+	SelectProportionalGain,
+	SelectIntegralGain,
+	SelectDerivativeGain,
+	/// Raise the gain that is selected in the inspector.
+	RaiseGain,
+	LowerGain,
+}
+
+/// A key, button or wheel that can trigger an [`Action`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Binding {
+	/// A key pressed on its own. Doesn't count while Ctrl is held, so that shortcuts don't also do whatever their key
+	/// does alone.
+	Key(KeyCode),
+	/// A key pressed while Ctrl is held.
+	Ctrl(KeyCode),
+	Mouse(MouseButton),
+	/// Scrolling the mouse wheel, by how many lines it is scrolled, up being positive.
+	Wheel,
+}
+
+/// Which bindings trigger each action.
+///
+/// Loaded from a RON map from actions to lists of bindings. Actions that are left out keep their default bindings:
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_playground::input::{Action, Binding, InputMap};
+/// let map = InputMap::from_ron("{ StartWave: [Key(Return)], Pause: [] }").unwrap();
+/// assert_eq!(map.bindings(Action::StartWave), &[Binding::Key(KeyCode::Return)]);
+/// assert!(map.bindings(Action::Pause).is_empty());
+/// assert_eq!(map.bindings(Action::Orbit), InputMap::default().bindings(Action::Orbit));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputMap {
+	bindings: HashMap<Action, Vec<Binding>>,
+}
+
+impl Default for InputMap {
+	/// Mouse controls for the camera, with keyboard alternatives for trackpads.
+	fn default() -> Self {
+		use Binding::*;
+		Self {
+			bindings: HashMap::from([
+				(Action::Orbit, vec![Mouse(MouseButton::Right)]),
+				(Action::Pan, vec![Mouse(MouseButton::Middle)]),
+				(Action::Zoom, vec![Wheel]),
+				(Action::OrbitLeft, vec![Key(KeyCode::Q)]),
+				(Action::OrbitRight, vec![Key(KeyCode::E)]),
+				(Action::PanLeft, vec![Key(KeyCode::A), Key(KeyCode::Left)]),
+				(Action::PanRight, vec![Key(KeyCode::D), Key(KeyCode::Right)]),
+				(Action::PanForward, vec![Key(KeyCode::W), Key(KeyCode::Up)]),
+				(Action::PanBack, vec![Key(KeyCode::S), Key(KeyCode::Down)]),
+				(Action::ZoomIn, vec![Key(KeyCode::Equals), Key(KeyCode::NumpadAdd)]),
+				(Action::ZoomOut, vec![Key(KeyCode::Minus), Key(KeyCode::NumpadSubtract)]),
+				(Action::SwitchCameraMode, vec![Key(KeyCode::C)]),
+				(Action::FocusCamera, vec![Key(KeyCode::F)]),
+				(Action::FollowWithCamera, vec![Key(KeyCode::G)]),
+				(Action::ResetCamera, vec![Key(KeyCode::Home)]),
+				(Action::ToggleHealthBars, vec![Key(KeyCode::H)]),
+				(Action::ToggleDamageNumbers, vec![Key(KeyCode::N)]),
+				(Action::StartWave, vec![Key(KeyCode::Space)]),
+				(Action::Pause, vec![Key(KeyCode::P), Key(KeyCode::Pause)]),
+				(Action::PlaceTower, vec![Mouse(MouseButton::Left)]),
+				(Action::ToggleEditor, vec![Key(KeyCode::F2)]),
+				(Action::SaveLevel, vec![Ctrl(KeyCode::S)]),
+				(Action::DeleteNode, vec![Key(KeyCode::Delete), Key(KeyCode::Back)]),
+				(Action::ToggleInspector, vec![Key(KeyCode::F3)]),
+				(Action::InspectNextTower, vec![Key(KeyCode::Tab)]),
+				(Action::SelectProportionalGain, vec![Key(KeyCode::Key1)]),
+				(Action::SelectIntegralGain, vec![Key(KeyCode::Key2)]),
+				(Action::SelectDerivativeGain, vec![Key(KeyCode::Key3)]),
+				(Action::RaiseGain, vec![Key(KeyCode::PageUp)]),
+				(Action::LowerGain, vec![Key(KeyCode::PageDown)]),
+			]),
+		}
+	}
+}
+
+impl InputMap {
+	/// The default bindings, with the actions in `ron` rebound.
+	pub fn from_ron(ron: &str) -> anyhow::Result<Self> {
+		let overrides: HashMap<Action, Vec<Binding>> = ron::de::from_str(ron)?;
+		let mut map = Self::default();
+		map.bindings.extend(overrides);
+		Ok(map)
+	}
+
+	pub fn load(path: &Path) -> anyhow::Result<Self> {
+		Self::from_ron(&std::fs::read_to_string(path)?)
+	}
+
+	pub fn bindings(&self, action: Action) -> &[Binding] {
+		self.bindings.get(&action).map(Vec::as_slice).unwrap_or_default()
+	}
+
+	/// Add another binding for `action`.
+	pub fn bind(&mut self, action: Action, binding: Binding) {
+		self.bindings.entry(action).or_default().push(binding);
+	}
+
+	/// Remove every binding for `action`.
+	pub fn unbind(&mut self, action: Action) {
+		self.bindings.insert(action, vec![]);
+	}
+}
+
+/// Which actions are happening this frame, according to the [`InputMap`].
+#[derive(Debug, Default)]
+pub struct ActionState {
+	/// How strongly every active action is happening. Buttons count as 1.
+	amounts: HashMap<Action, f32>,
+	previous: HashSet<Action>,
+}
+
+impl ActionState {
+	pub fn pressed(&self, action: Action) -> bool {
+		self.amounts.contains_key(&action)
+	}
+
+	pub fn just_pressed(&self, action: Action) -> bool {
+		self.pressed(action) && !self.previous.contains(&action)
+	}
+
+	pub fn just_released(&self, action: Action) -> bool {
+		!self.pressed(action) && self.previous.contains(&action)
+	}
+
+	/// How strongly `action` is happening, like how far the wheel was scrolled, or 0 if it isn't.
+	pub fn amount(&self, action: Action) -> f32 {
+		self.amounts.get(&action).copied().unwrap_or(0.)
+	}
+
+	/// Start a new frame, in which only the actions in `amounts` are happening.
+	fn update(&mut self, amounts: HashMap<Action, f32>) {
+		self.previous = self.amounts.keys().copied().collect();
+		self.amounts = amounts;
+	}
+}
+
+/// Keeps the [`ActionState`] up to date. Adding it more than once is harmless.
+///
+/// Works without a window or mouse, in which case only keyboard bindings do anything.
+pub struct InputActionPlugin;
+
+impl Plugin for InputActionPlugin {
+	fn build(&self, app: &mut App) {
+		if app.world.contains_resource::<ActionState>() {
+			return;
+		}
+		app.init_resource::<InputMap>()
+			.init_resource::<ActionState>()
+			.add_system_to_stage(CoreStage::PreUpdate, update_actions.after(InputSystem));
+	}
+}
+
+pub fn update_actions(
+	input_map: Res<InputMap>,
+	keys: Option<Res<Input<KeyCode>>>,
+	mouse_buttons: Option<Res<Input<MouseButton>>>,
+	wheel_events: Option<Res<Events<MouseWheel>>>,
+	mut wheel_reader: Local<ManualEventReader<MouseWheel>>,
+	mut actions: ResMut<ActionState>,
+) {
+	let wheel: f32 = wheel_events
+		.map(|events| wheel_reader.iter(&events).map(|event| event.y).sum())
+		.unwrap_or(0.);
+	let key_pressed = |key| keys.as_ref().is_some_and(|keys| keys.pressed(key));
+	let ctrl = key_pressed(KeyCode::LControl) || key_pressed(KeyCode::RControl);
+	let mut amounts = HashMap::new();
+	for (action, bindings) in input_map.bindings.iter() {
+		let amount: f32 = bindings.iter()
+			.map(|binding| match *binding {
+				Binding::Key(key) => (!ctrl && key_pressed(key)) as u8 as f32,
+				Binding::Ctrl(key) => (ctrl && key_pressed(key)) as u8 as f32,
+				Binding::Mouse(button) => mouse_buttons.as_ref().is_some_and(|buttons| buttons.pressed(button)) as u8 as f32,
+				Binding::Wheel => wheel,
+			})
+			.sum();
+		if amount != 0. {
+			amounts.insert(*action, amount);
+		}
+	}
+	actions.update(amounts);
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_shipped_bindings_are_the_defaults() {
+		let map = InputMap::from_ron(include_str!("../assets/input.ron")).unwrap();
+		assert_eq!(map, InputMap::default());
+	}
+
+	#[test]
+	fn test_update_actions() {
+		let mut world = World::new();
+		world.init_resource::<ActionState>();
+		world.init_resource::<Input<KeyCode>>();
+		world.insert_resource(Events::<MouseWheel>::default());
+		let mut input_map = InputMap::default();
+		input_map.bind(Action::StartWave, Binding::Key(KeyCode::Return));
+		world.insert_resource(input_map);
+		let mut stage = SystemStage::single_threaded().with_system(update_actions);
+
+		world.resource_mut::<Input<KeyCode>>().press(KeyCode::Return);
+		world.resource_mut::<Events<MouseWheel>>().send(MouseWheel { unit: bevy::input::mouse::MouseScrollUnit::Line, x: 0., y: -2. });
+		stage.run(&mut world);
+		let actions = world.resource::<ActionState>();
+		assert!(actions.just_pressed(Action::StartWave));
+		assert_eq!(actions.amount(Action::Zoom), -2.);
+		// there is no mouse, so nothing is bound to it
+		assert!(!actions.pressed(Action::PlaceTower));
+
+		stage.run(&mut world);
+		let actions = world.resource::<ActionState>();
+		assert!(actions.pressed(Action::StartWave) && !actions.just_pressed(Action::StartWave));
+		// the scroll was already counted
+		assert!(!actions.pressed(Action::Zoom));
+
+		world.resource_mut::<Input<KeyCode>>().release(KeyCode::Return);
+		stage.run(&mut world);
+		assert!(world.resource::<ActionState>().just_released(Action::StartWave));
+	}
+
+	#[test]
+	fn test_ctrl_bindings() {
+		let mut world = World::new();
+		world.init_resource::<ActionState>();
+		world.init_resource::<Input<KeyCode>>();
+		world.init_resource::<InputMap>();
+		let mut stage = SystemStage::single_threaded().with_system(update_actions);
+
+		world.resource_mut::<Input<KeyCode>>().press(KeyCode::S);
+		stage.run(&mut world);
+		assert!(world.resource::<ActionState>().pressed(Action::PanBack));
+		assert!(!world.resource::<ActionState>().pressed(Action::SaveLevel));
+
+		// the shortcut, and not the key on its own
+		world.resource_mut::<Input<KeyCode>>().press(KeyCode::LControl);
+		stage.run(&mut world);
+		assert!(!world.resource::<ActionState>().pressed(Action::PanBack));
+		assert!(world.resource::<ActionState>().just_pressed(Action::SaveLevel));
+	}
+
+	#[test]
+	fn test_default_bindings_dont_overlap() {
+		let map = InputMap::default();
+		let mut seen = HashMap::new();
+		for (action, bindings) in map.bindings.iter() {
+			for binding in bindings {
+				if let Some(other) = seen.insert(*binding, *action) {
+					panic!("{:?} is bound to both {:?} and {:?}", binding, action, other);
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,4 @@
+pub mod camera;
+pub mod input;
+pub mod pid_controller;
+pub mod tower_defense;
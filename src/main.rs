@@ -1,13 +1,22 @@
 use bevy::prelude::*;
+use bevy::asset::AssetServerSettings;
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
 
+use bevy_playground::tower_defense;
+
 mod hello;
-mod tower_defense;
-mod pid_controller;
-mod camera;
 
 fn main() {
+    // the level to play can be picked by passing its name as the first argument
+    let level_name = std::env::args().nth(1).unwrap_or_else(|| "default".to_string());
+
     App::new()
+        .insert_resource(AssetServerSettings {
+            // reload levels when their files are edited
+            watch_for_changes: true,
+            ..Default::default()
+        })
+        .insert_resource(tower_defense::level::CurrentLevel::new(&level_name))
         .add_plugins(DefaultPlugins)
         // .add_plugin(LogDiagnosticsPlugin::default())
         // .add_plugin(FrameTimeDiagnosticsPlugin::default())
@@ -1,71 +1,60 @@
+use std::marker::PhantomData;
 use std::ops::{Add, Div, Mul, Sub};
 
 use bevy::prelude::*;
 
-/// A Proportional Integral Derivative Controller for an object's position.
-///
-/// The controller is configured with a set of gains. The controller will attempt to maintain a
-/// target value by adjusting the output of the controlled system.
+pub mod tuning;
+
+/// A value that can be controlled by a [`PidControlled`].
 ///
-/// see: https://en.wikipedia.org/wiki/PID_controller
-#[derive(Component, Debug)]
-pub struct PidControlledPosition {
-	pub proportional_gain: f32,
-	pub integral_gain: f32,
-	pub derivative_gain: f32,
+/// Multiplying two values multiplies them component by component, which is how per-axis gains are applied.
+pub trait PidValue: Default + Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Mul<f32, Output = Self> + Div<f32, Output = Self> {
+	/// A value with every component set to `value`.
+	fn splat(value: f32) -> Self;
+	fn magnitude(self) -> f32;
+	/// Scale the value down so that its magnitude is no more than `max`.
+	fn clamp_magnitude(self, max: f32) -> Self;
+}
 
-	target: Vec3,
+impl PidValue for f32 {
+	fn splat(value: f32) -> Self {
+		value
+	}
 
-	integration: Vec3,
-	error_prev: Vec3,
-	value_prev: Vec3,
-	derivative_initialized: bool,
+	fn magnitude(self) -> f32 {
+		self.abs()
+	}
+
+	fn clamp_magnitude(self, max: f32) -> Self {
+		self.clamp(-max, max)
+	}
 }
 
-impl PidControlledPosition {
-	pub fn new(proportional_gain: f32, integral_gain: f32, derivative_gain: f32) -> Self {
-		Self {
-			proportional_gain,
-			integral_gain,
-			derivative_gain,
-			target: Vec3::new(0.0, 0.0, 0.0),
-			integration: Vec3::new(0.0, 0.0, 0.0),
-			error_prev: Vec3::new(0.0, 0.0, 0.0),
-			value_prev: Vec3::new(0.0, 0.0, 0.0),
-			derivative_initialized: false,
-		}
+impl PidValue for Vec2 {
+	fn splat(value: f32) -> Self {
+		Self::splat(value)
 	}
 
-	pub fn compute(&mut self, delta_time: f32, current_value: Vec3) -> Vec3 {
-		let error = self.target - current_value;
-		let error_rate_of_change = (error - self.error_prev) / delta_time;
-		let derive_measure = if self.derivative_initialized {
-			error_rate_of_change
-		} else {
-			Vec3::new(0., 0., 0.)
-		};
-		self.integration += error * delta_time;
-		let p = self.proportional_gain * error;
-		let i = self.integral_gain * self.integration;
-		let d = self.derivative_gain * derive_measure;
-		let output = p + i + d;
-		self.error_prev = error;
-		self.value_prev = current_value;
-		output
+	fn magnitude(self) -> f32 {
+		self.length()
 	}
 
-	pub fn set_target(&mut self, target: Vec3) {
-		self.target = target;
+	fn clamp_magnitude(self, max: f32) -> Self {
+		self.clamp_length_max(max)
 	}
 }
 
-pub fn system(
-	time: Res<Time>,
-	mut controllers: Query<(&mut PidControlledPosition, &mut Transform)>,
-) {
-	for (mut controller, mut transform) in controllers.iter_mut() {
-		let output = controller.compute(time.delta_seconds(), transform.translation);
-		transform.translation += output;
+impl PidValue for Vec3 {
+	fn splat(value: f32) -> Self {
+		Self::splat(value)
+	}
+
+	fn magnitude(self) -> f32 {
+		self.length()
+	}
+
+	fn clamp_magnitude(self, max: f32) -> Self {
+		self.clamp_length_max(max)
 	}
 }
 
@@ -74,74 +63,448 @@ pub fn system(
 /// The controller is configured with a set of gains. The controller will attempt to maintain a
 /// target value by adjusting the output of the controlled system.
 ///
-/// `T` is the type of the value being controlled, and `I` is a constant that is used to indicate what the value controls.
-/// `I` is not used in the computation of the controller, but is used to indicate to the programmer what the value controls (eg. "position"),
-/// and so that you can use `PidControlled` to control any component value.
+/// `T` is the type of the value being controlled, and `C` is a marker type that says what the value controls (eg. [`Translation`]).
+/// `C` is not used in the computation of the controller, but lets one entity have several controllers for values of the same type.
+/// Implement [`PidChannel`] for `C` and add a [`PidPlugin`] to have the controller drive a field of a component.
+///
+/// The derivative is taken of the measured value instead of the error, so that changing the target doesn't
+/// cause a sudden kick in the output.
+///
+/// The gains are of the same type as the value, and are applied to each component separately, so for example
+/// a `Vec3` controller can react more strongly along one axis than the others. See [`with_axis_gains`](Self::with_axis_gains).
 ///
 /// see: https://en.wikipedia.org/wiki/PID_controller
 #[derive(Component, Debug)]
-pub struct PidControlled<T, const I: u64> {  // Maybe it would be better to accept an enum for I?
-	pub proportional_gain: f32,
-	pub integral_gain: f32,
-	pub derivative_gain: f32,
+pub struct PidControlled<T, C> {
+	pub proportional_gain: T,
+	pub integral_gain: T,
+	pub derivative_gain: T,
+	/// Largest magnitude that the integral term can reach, so that it doesn't wind up while the target can't be reached.
+	pub integral_limit: Option<f32>,
+	/// Largest magnitude of the output.
+	pub output_limit: Option<f32>,
+	/// Time constant in seconds of the low-pass filter on the derivative term. Zero turns off filtering.
+	pub derivative_filter: f32,
 
 	target: T,
 
 	integration: T,
-	error_prev: T,
 	value_prev: T,
+	derivative: T,
 	derivative_initialized: bool,
+	// `fn() -> C` is always Send and Sync, so the marker doesn't have to be
+	channel: PhantomData<fn() -> C>,
 }
 
-unsafe impl<T, const I: u64> Send for PidControlled<T, I> {}
-unsafe impl<T, const I: u64> Sync for PidControlled<T, I> {}
-
-impl<T, const I: u64> PidControlled<T, I> where
-	T: Default + Copy + Sync + Send + Add<Output = T> + Sub<Output = T> + Mul<Output = T> + Mul<f32, Output = T> + Div<Output = T> + Div<f32, Output = T> {
+impl<T: PidValue, C> PidControlled<T, C> {
+	/// The same gains for every axis.
 	pub fn new(proportional_gain: f32, integral_gain: f32, derivative_gain: f32) -> Self {
+		Self::with_axis_gains(T::splat(proportional_gain), T::splat(integral_gain), T::splat(derivative_gain))
+	}
+
+	/// Separate gains for every axis.
+	pub fn with_axis_gains(proportional_gain: T, integral_gain: T, derivative_gain: T) -> Self {
 		Self {
 			proportional_gain,
 			integral_gain,
 			derivative_gain,
+			integral_limit: None,
+			output_limit: None,
+			derivative_filter: 0.,
 			target: Default::default(),
 			integration: Default::default(),
-			error_prev: Default::default(),
 			value_prev: Default::default(),
+			derivative: Default::default(),
 			derivative_initialized: false,
+			channel: PhantomData,
 		}
 	}
 
+	pub fn with_integral_limit(mut self, limit: f32) -> Self {
+		self.integral_limit = Some(limit);
+		self
+	}
+
+	pub fn with_output_limit(mut self, limit: f32) -> Self {
+		self.output_limit = Some(limit);
+		self
+	}
+
+	pub fn with_derivative_filter(mut self, time_constant: f32) -> Self {
+		self.derivative_filter = time_constant;
+		self
+	}
+
 	pub fn compute(&mut self, delta_time: f32, current_value: T) -> T {
 		let error = self.target - current_value;
-		let error_rate_of_change = (error - self.error_prev) / delta_time;
-		let derive_measure = if self.derivative_initialized {
-			error_rate_of_change
-		} else {
-			Default::default()
+		if delta_time <= 0. {
+			// no time has passed, so there is nothing to integrate or differentiate
+			return self.saturate(self.proportional_gain * error + self.integral_gain * self.integration);
+		}
+
+		if self.derivative_initialized {
+			// the error changes as fast as the value does, but in the opposite direction, unless the target moved
+			let rate_of_change = (self.value_prev - current_value) / delta_time;
+			let smoothing = delta_time / (self.derivative_filter.max(0.) + delta_time);
+			self.derivative = self.derivative + (rate_of_change - self.derivative) * smoothing;
+		}
+
+		let integration = self.integration + error * delta_time; // not using += so we can require less trait bounds
+		let integration = match self.integral_limit {
+			Some(limit) => integration.clamp_magnitude(limit),
+			None => integration,
 		};
-		self.integration = self.integration + error * delta_time; // not using += so we can require less trait bounds
-		let p = error * self.proportional_gain;
-		let i = self.integration * self.integral_gain;
-		let d = derive_measure * self.derivative_gain;
+		let p = self.proportional_gain * error;
+		let i = self.integral_gain * integration;
+		let d = self.derivative_gain * self.derivative;
 		let output = p + i + d;
-		self.error_prev = error;
+		let saturated = self.saturate(output);
+		// anti-windup: stop integrating while the output is already as large as it can get
+		if self.output_limit.is_none_or(|limit| output.magnitude() <= limit) {
+			self.integration = integration;
+		}
 		self.value_prev = current_value;
-		output
+		self.derivative_initialized = true;
+		saturated
+	}
+
+	fn saturate(&self, output: T) -> T {
+		match self.output_limit {
+			Some(limit) => output.clamp_magnitude(limit),
+			None => output,
+		}
 	}
 
 	pub fn set_target(&mut self, target: T) {
 		self.target = target;
 	}
+
+	pub fn target(&self) -> T {
+		self.target
+	}
+
+	/// Forget the accumulated integral and the previous measurement, like when the controller has just been created.
+	/// The gains, limits and target are kept.
+	pub fn reset(&mut self) {
+		self.integration = Default::default();
+		self.value_prev = Default::default();
+		self.derivative = Default::default();
+		self.derivative_initialized = false;
+	}
+}
+
+/// Says which value a [`PidControlled<_, Self>`](PidControlled) drives: a field of a component on the same entity.
+pub trait PidChannel: 'static {
+	type Value: PidValue + Send + Sync + 'static;
+	type Component: Component;
+
+	fn value(component: &mut Self::Component) -> &mut Self::Value;
+}
+
+/// Drives an entity's [`Transform::translation`] towards the controller's target.
+#[derive(Debug)]
+pub struct Translation;
+
+impl PidChannel for Translation {
+	type Value = Vec3;
+	type Component = Transform;
+
+	fn value(transform: &mut Transform) -> &mut Vec3 {
+		&mut transform.translation
+	}
+}
+
+/// Adds the output of every [`PidControlled`] on channel `C` to the value that it controls, every frame.
+#[allow(clippy::type_complexity)]
+pub fn drive<C: PidChannel>(
+	time: Res<Time>,
+	mut controllers: Query<(&mut PidControlled<C::Value, C>, &mut C::Component)>,
+) {
+	for (mut controller, mut component) in controllers.iter_mut() {
+		let value = C::value(&mut component);
+		*value = *value + controller.compute(time.delta_seconds(), *value);
+	}
+}
+
+/// Registers [`drive`] for the channel `C`.
+pub struct PidPlugin<C>(PhantomData<fn() -> C>);
+
+impl<C> Default for PidPlugin<C> {
+	fn default() -> Self {
+		Self(PhantomData)
+	}
+}
+
+impl<C: PidChannel> Plugin for PidPlugin<C> {
+	fn build(&self, app: &mut App) {
+		app.add_system(drive::<C>);
+	}
+}
+
+/// Two controllers in a row: the position loop turns how far the value is from its target into the velocity to
+/// move at, and the velocity loop turns how far the measured velocity is from that into an acceleration.
+///
+/// Limiting the output of the position loop bounds the velocity, and limiting the output of the velocity loop
+/// bounds the acceleration, so the value gets to its target smoothly however far away it is.
+/// `C` works the same as it does for [`PidControlled`].
+#[derive(Component, Debug)]
+pub struct PidCascade<T, C> {
+	pub position: PidControlled<T, C>,
+	pub velocity: PidControlled<T, C>,
+}
+
+impl<T: PidValue, C> PidCascade<T, C> {
+	pub fn new(position: PidControlled<T, C>, velocity: PidControlled<T, C>) -> Self {
+		Self {
+			position,
+			velocity,
+		}
+	}
+
+	pub fn set_target(&mut self, target: T) {
+		self.position.set_target(target);
+	}
+
+	pub fn target(&self) -> T {
+		self.position.target()
+	}
+
+	/// The acceleration to apply, given where the value is and how fast it is changing.
+	pub fn compute(&mut self, delta_time: f32, position: T, velocity: T) -> T {
+		let target_velocity = self.position.compute(delta_time, position);
+		self.velocity.set_target(target_velocity);
+		self.velocity.compute(delta_time, velocity)
+	}
+
+	pub fn reset(&mut self) {
+		self.position.reset();
+		self.velocity.reset();
+	}
+}
+
+/// Turns an orientation towards a target orientation, at no more than a maximum angular velocity.
+///
+/// The error is the rotation that is left to do, as an axis scaled by the angle around it in radians,
+/// and the output of the inner controller is an angular velocity in radians per second.
+/// `C` works the same as it does for [`PidControlled`].
+///
+/// [`drive_rotation`] writes straight to the entity's `Transform`, so it doesn't work for a
+/// [`PanOrbitCamera`](crate::camera::PanOrbitCamera), whose transform is set from its yaw and pitch every frame.
+#[derive(Component, Debug)]
+pub struct PidControlledRotation<C> {
+	pub controller: PidControlled<Vec3, C>,
+	target: Quat,
 }
 
-pub const PID_CONTROL_POSITION: u64 = 0;
+impl<C> PidControlledRotation<C> {
+	/// `max_angular_velocity` is in radians per second.
+	pub fn new(proportional_gain: f32, integral_gain: f32, derivative_gain: f32, max_angular_velocity: f32) -> Self {
+		Self {
+			controller: PidControlled::new(proportional_gain, integral_gain, derivative_gain)
+				.with_output_limit(max_angular_velocity),
+			target: Quat::IDENTITY,
+		}
+	}
+
+	pub fn set_target(&mut self, target: Quat) {
+		self.target = target.normalize();
+	}
+
+	pub fn target(&self) -> Quat {
+		self.target
+	}
+
+	/// The rotation from `current` to the target, the short way around, as a scaled axis.
+	fn error(&self, current: Quat) -> Vec3 {
+		let difference = self.target * current.inverse();
+		// `q` and `-q` are the same orientation, but only one of them doesn't go the long way around
+		let difference = if difference.w < 0. { -difference } else { difference };
+		difference.to_scaled_axis()
+	}
+
+	/// Angle in radians between `current` and the target.
+	pub fn angle_to_target(&self, current: Quat) -> f32 {
+		self.error(current).length()
+	}
+
+	/// Whether `current` is within `tolerance` radians of the target.
+	pub fn is_aimed(&self, current: Quat, tolerance: f32) -> bool {
+		self.angle_to_target(current) <= tolerance
+	}
+
+	/// The orientation after turning from `current` for `delta_time` seconds.
+	pub fn compute(&mut self, delta_time: f32, current: Quat) -> Quat {
+		// the controller's target is no error left, so the measured value is the error turned around
+		let angular_velocity = self.controller.compute(delta_time, -self.error(current));
+		let step = angular_velocity * delta_time;
+		// don't overshoot when the step is bigger than what is left
+		let remaining = self.angle_to_target(current);
+		let step = if step.length() > remaining && remaining > 0. {
+			step.normalize() * remaining
+		} else {
+			step
+		};
+		(Quat::from_scaled_axis(step) * current).normalize()
+	}
+}
 
-pub fn system_pid_controller_position(
+/// Turns every entity with a [`PidControlledRotation`] on channel `C` towards the controller's target.
+pub fn drive_rotation<C: 'static>(
 	time: Res<Time>,
-	mut controllers: Query<(&mut PidControlled<Vec3, PID_CONTROL_POSITION>, &mut Transform)>,
+	mut controllers: Query<(&mut PidControlledRotation<C>, &mut Transform)>,
 ) {
 	for (mut controller, mut transform) in controllers.iter_mut() {
-		let output = controller.compute(time.delta_seconds(), transform.translation);
-		transform.translation += output;
+		transform.rotation = controller.compute(time.delta_seconds(), transform.rotation);
+	}
+}
+
+/// Registers [`drive_rotation`] for the channel `C`.
+pub struct PidRotationPlugin<C>(PhantomData<fn() -> C>);
+
+impl<C> Default for PidRotationPlugin<C> {
+	fn default() -> Self {
+		Self(PhantomData)
+	}
+}
+
+impl<C: 'static> Plugin for PidRotationPlugin<C> {
+	fn build(&self, app: &mut App) {
+		app.add_system(drive_rotation::<C>);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_no_derivative_kick() {
+		let mut controller = PidControlled::<f32, ()>::new(0., 0., 1.);
+		assert_eq!(controller.compute(0.1, 0.), 0.);
+		controller.set_target(10.);
+		// only the measured value counts, so a new target doesn't cause a spike
+		assert_eq!(controller.compute(0.1, 0.), 0.);
+		// the value moving towards the target is damped
+		assert!(controller.compute(0.1, 1.) < 0.);
+	}
+
+	#[test]
+	fn test_integral_limit() {
+		let mut controller = PidControlled::<f32, ()>::new(0., 1., 0.).with_integral_limit(2.);
+		controller.set_target(10.);
+		for _ in 0..100 {
+			controller.compute(0.1, 0.);
+		}
+		assert_eq!(controller.compute(0.1, 0.), 2.);
+	}
+
+	#[test]
+	fn test_output_limit_stops_windup() {
+		let mut controller = PidControlled::<Vec3, ()>::new(1., 1., 0.).with_output_limit(1.);
+		controller.set_target(Vec3::new(10., 0., 0.));
+		for _ in 0..100 {
+			let output = controller.compute(0.1, Vec3::ZERO);
+			assert!(output.length() <= 1. + f32::EPSILON);
+		}
+		// nothing was integrated while saturated, so the output drops as soon as the target is reached
+		assert_eq!(controller.compute(0.1, Vec3::new(10., 0., 0.)), Vec3::ZERO);
+	}
+
+	#[test]
+	fn test_derivative_filter() {
+		let mut unfiltered = PidControlled::<f32, ()>::new(0., 0., 1.);
+		let mut filtered = PidControlled::<f32, ()>::new(0., 0., 1.).with_derivative_filter(0.5);
+		unfiltered.compute(0.1, 0.);
+		filtered.compute(0.1, 0.);
+		let sharp = unfiltered.compute(0.1, 1.);
+		let smooth = filtered.compute(0.1, 1.);
+		assert!(smooth < 0. && smooth > sharp);
+	}
+
+	#[test]
+	fn test_reset() {
+		let mut controller = PidControlled::<f32, ()>::new(1., 1., 1.);
+		controller.set_target(5.);
+		controller.compute(0.1, 0.);
+		controller.compute(0.1, 2.);
+		controller.reset();
+		assert_eq!(controller.target(), 5.);
+		// behaves like a new controller
+		assert_eq!(controller.compute(0.1, 0.), 5. + 0.5);
+	}
+
+	#[test]
+	fn test_axis_gains() {
+		let mut controller = PidControlled::<Vec3, ()>::with_axis_gains(Vec3::new(1., 2., 0.), Vec3::ZERO, Vec3::ZERO);
+		controller.set_target(Vec3::ONE);
+		assert_eq!(controller.compute(0.1, Vec3::ZERO), Vec3::new(1., 2., 0.));
+		// the same gain on every axis
+		let mut uniform = PidControlled::<Vec3, ()>::new(3., 0., 0.);
+		uniform.set_target(Vec3::ONE);
+		assert_eq!(uniform.compute(0.1, Vec3::ZERO), Vec3::splat(3.));
+	}
+
+	#[test]
+	fn test_cascade_bounded_velocity() {
+		let max_speed = 2.;
+		let mut cascade = PidCascade::<Vec3, ()>::new(
+			PidControlled::new(2., 0., 0.).with_output_limit(max_speed),
+			PidControlled::new(10., 0., 0.).with_output_limit(20.),
+		);
+		cascade.set_target(Vec3::new(10., 0., 0.));
+		let (mut position, mut velocity) = (Vec3::ZERO, Vec3::ZERO);
+		let delta = 0.01;
+		for _ in 0..1000 {
+			let acceleration = cascade.compute(delta, position, velocity);
+			assert!(acceleration.length() <= 20. + 0.001);
+			velocity += acceleration * delta;
+			// the velocity loop chases the limited target velocity, so it barely overshoots it
+			assert!(velocity.length() <= max_speed * 1.05, "{}", velocity);
+			position += velocity * delta;
+		}
+		assert!(position.distance(Vec3::new(10., 0., 0.)) < 0.01, "{}", position);
+	}
+
+	#[test]
+	fn test_drive_translation() {
+		let mut world = World::new();
+		world.insert_resource(Time::default());
+		let mut controller = PidControlled::<Vec3, Translation>::new(1., 0., 0.);
+		controller.set_target(Vec3::X);
+		let entity = world.spawn()
+			.insert(controller)
+			.insert(Transform::default())
+			.id();
+		SystemStage::single_threaded()
+			.with_system(drive::<Translation>)
+			.run(&mut world);
+		// moved all the way to the target, because the proportional gain is 1
+		assert_eq!(world.get::<Transform>(entity).unwrap().translation, Vec3::X);
+	}
+
+	#[test]
+	fn test_rotation_max_angular_velocity() {
+		let mut controller = PidControlledRotation::<()>::new(100., 0., 0., 1.);
+		controller.set_target(Quat::from_rotation_z(std::f32::consts::FRAC_PI_2));
+		let turned = controller.compute(0.5, Quat::IDENTITY);
+		// a huge gain, but no faster than one radian per second
+		assert!((turned.angle_between(Quat::IDENTITY) - 0.5).abs() < 0.0001);
+		assert!(!controller.is_aimed(turned, 0.5));
+		assert!(controller.is_aimed(turned, 1.1));
+	}
+
+	#[test]
+	fn test_rotation_reaches_target_the_short_way() {
+		let mut controller = PidControlledRotation::<()>::new(5., 0., 0., std::f32::consts::PI);
+		// just short of turning all the way around, which is shorter to do the other way
+		let target = Quat::from_rotation_y(std::f32::consts::PI * 1.9);
+		controller.set_target(target);
+		let mut rotation = Quat::IDENTITY;
+		let first = controller.compute(0.01, rotation);
+		assert!(first.to_scaled_axis().y < 0.);
+		for _ in 0..200 {
+			rotation = controller.compute(0.05, rotation);
+		}
+		assert!(controller.angle_to_target(rotation) < 0.001);
 	}
 }
@@ -0,0 +1,198 @@
+//! Finding gains for a [`PidControlled`](super::PidControlled) by experimenting on a simulation of the controlled system.
+
+use std::f32::consts::PI;
+
+/// Gains for a [`PidControlled`](super::PidControlled).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PidGains {
+	pub proportional: f32,
+	pub integral: f32,
+	pub derivative: f32,
+}
+
+/// Relay feedback auto-tuning.
+///
+/// Instead of a controller, the system is driven by a fixed output that flips sign whenever the measured value
+/// crosses the setpoint. Most systems settle into an oscillation, and its period and amplitude tell how much
+/// proportional gain it takes to make the system oscillate on its own. The Ziegler–Nichols rules turn that into gains.
+///
+/// see: https://en.wikipedia.org/wiki/Ziegler%E2%80%93Nichols_method
+#[derive(Debug, Clone, Copy)]
+pub struct RelayAutotune {
+	/// The value that the system is driven around.
+	pub setpoint: f32,
+	/// Size of the output that is switched back and forth.
+	pub relay_output: f32,
+	/// How far past the setpoint the value has to get before the output switches, so that noise can't make it chatter.
+	pub hysteresis: f32,
+	/// Seconds per simulated step.
+	pub delta_time: f32,
+	/// Give up after simulating this many seconds.
+	pub max_time: f32,
+	/// How many oscillations in a row are measured.
+	pub cycles: usize,
+	/// The system has settled into an oscillation once the periods of the measured oscillations are this close together,
+	/// relative to their average.
+	pub tolerance: f32,
+}
+
+impl Default for RelayAutotune {
+	fn default() -> Self {
+		Self {
+			setpoint: 0.,
+			relay_output: 1.,
+			hysteresis: 0.,
+			delta_time: 0.001,
+			max_time: 120.,
+			cycles: 4,
+			tolerance: 0.02,
+		}
+	}
+}
+
+/// What a [`RelayAutotune`] found out about the system.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutotuneResult {
+	/// Proportional gain at which the system keeps oscillating.
+	pub ultimate_gain: f32,
+	/// Seconds per oscillation at the ultimate gain.
+	pub ultimate_period: f32,
+}
+
+impl AutotuneResult {
+	/// The classic Ziegler–Nichols gains. Reacts quickly, but overshoots.
+	pub fn classic_pid(&self) -> PidGains {
+		let proportional = 0.6 * self.ultimate_gain;
+		PidGains {
+			proportional,
+			integral: proportional * 2. / self.ultimate_period,
+			derivative: proportional * self.ultimate_period / 8.,
+		}
+	}
+
+	/// Ziegler–Nichols gains without an integral term, for systems that don't drift away from where they are left,
+	/// like something that has to be pushed to move.
+	pub fn pd(&self) -> PidGains {
+		let proportional = 0.8 * self.ultimate_gain;
+		PidGains {
+			proportional,
+			integral: 0.,
+			derivative: proportional * self.ultimate_period / 8.,
+		}
+	}
+
+	/// Ziegler–Nichols gains for little to no overshoot, at the cost of reacting more slowly.
+	pub fn no_overshoot(&self) -> PidGains {
+		let proportional = 0.2 * self.ultimate_gain;
+		PidGains {
+			proportional,
+			integral: proportional * 2. / self.ultimate_period,
+			derivative: proportional * self.ultimate_period / 3.,
+		}
+	}
+}
+
+impl RelayAutotune {
+	/// `plant(output, delta_time)` applies `output` to the simulated system for `delta_time` seconds, and returns the
+	/// measured value afterwards.
+	///
+	/// Oscillations are measured until the last [`cycles`](Self::cycles) of them are about as long as each other.
+	/// Returns `None` if the system doesn't settle into an oscillation within [`max_time`](Self::max_time).
+	pub fn run(&self, mut plant: impl FnMut(f32, f32) -> f32) -> Option<AutotuneResult> {
+		let mut output = self.relay_output;
+		let mut time = 0.;
+		let mut last_switch_up: Option<f32> = None;
+		let (mut highest, mut lowest) = (f32::NEG_INFINITY, f32::INFINITY);
+		// (period, amplitude) of every full oscillation
+		let mut oscillations = vec![];
+
+		while time < self.max_time {
+			let value = plant(output, self.delta_time);
+			time += self.delta_time;
+			if !value.is_finite() {
+				return None;
+			}
+			highest = highest.max(value);
+			lowest = lowest.min(value);
+
+			let error = self.setpoint - value;
+			if output > 0. && error < -self.hysteresis {
+				output = -self.relay_output;
+			} else if output < 0. && error > self.hysteresis {
+				output = self.relay_output;
+				// a full oscillation is from one upwards switch to the next
+				if let Some(last) = last_switch_up {
+					oscillations.push((time - last, (highest - lowest) / 2.));
+					if let Some(result) = self.settled(&oscillations) {
+						return Some(result);
+					}
+				}
+				last_switch_up = Some(time);
+				highest = f32::NEG_INFINITY;
+				lowest = f32::INFINITY;
+			}
+		}
+		None
+	}
+
+	/// The result, if the last few `(period, amplitude)` oscillations are steady.
+	fn settled(&self, oscillations: &[(f32, f32)]) -> Option<AutotuneResult> {
+		if self.cycles == 0 || oscillations.len() < self.cycles {
+			return None;
+		}
+		let measured = &oscillations[oscillations.len() - self.cycles..];
+		let count = measured.len() as f32;
+		let period = measured.iter().map(|(period, _)| period).sum::<f32>() / count;
+		let amplitude = measured.iter().map(|(_, amplitude)| amplitude).sum::<f32>() / count;
+		let steady = measured.iter().all(|(measured_period, _)| (measured_period - period).abs() <= period * self.tolerance);
+		if !steady || amplitude <= self.hysteresis {
+			return None;
+		}
+		// describing function of a relay with hysteresis
+		let ultimate_gain = 4. * self.relay_output / (PI * (amplitude * amplitude - self.hysteresis * self.hysteresis).sqrt());
+		Some(AutotuneResult {
+			ultimate_gain,
+			ultimate_period: period,
+		})
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// Three first order lags in a row, 1 / (s + 1)^3, which oscillates at a gain of 8 with a period of 2π / √3.
+	fn third_order_lag() -> impl FnMut(f32, f32) -> f32 {
+		let mut state = [0f32; 3];
+		move |output, delta_time| {
+			state[0] += (output - state[0]) * delta_time;
+			state[1] += (state[0] - state[1]) * delta_time;
+			state[2] += (state[1] - state[2]) * delta_time;
+			state[2]
+		}
+	}
+
+	#[test]
+	fn test_relay_autotune_third_order_lag() {
+		let result = RelayAutotune::default().run(third_order_lag()).unwrap();
+		let expected_period = 2. * PI / 3f32.sqrt();
+		assert!((result.ultimate_period - expected_period).abs() / expected_period < 0.1, "{:?}", result);
+		// the relay experiment is an approximation, but a close one
+		assert!((result.ultimate_gain - 8.).abs() / 8. < 0.2, "{:?}", result);
+
+		let gains = result.classic_pid();
+		assert!((gains.proportional - 0.6 * result.ultimate_gain).abs() < 0.0001);
+		assert!(result.no_overshoot().proportional < gains.proportional);
+	}
+
+	#[test]
+	fn test_relay_autotune_no_oscillation() {
+		// a system that never crosses the setpoint
+		let result = RelayAutotune {
+			setpoint: 10.,
+			max_time: 5.,
+			..Default::default()
+		}.run(|_, _| 0.);
+		assert_eq!(result, None);
+	}
+}
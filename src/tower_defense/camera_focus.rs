@@ -0,0 +1,73 @@
+use bevy::{prelude::*, render::camera::Camera3d};
+
+use crate::camera::{self, CameraCommand, CameraView, Ray};
+use crate::input::{Action, ActionState};
+
+use super::enemy::Enemy;
+use super::inspector::PidInspector;
+use super::towers::Tower;
+
+/// How close the cursor has to be to a tower or enemy to pick it.
+const PICK_RADIUS: f32 = 1.;
+
+/// The view for levels that don't set their own: looking straight down from high enough to see the whole map.
+pub const DEFAULT_VIEW: CameraView = CameraView {
+	focus: Vec3::ZERO,
+	radius: 20.,
+	yaw: 0.,
+	pitch: -std::f32::consts::FRAC_PI_2,
+};
+
+/// Point the camera at towers and enemies.
+///
+/// - F ([`Action::FocusCamera`]): focus on the tower or enemy under the cursor, or the tower selected in the inspector
+/// - G ([`Action::FollowWithCamera`]): follow the enemy under the cursor, or stop following
+/// - Home ([`Action::ResetCamera`]): go back to the level's view
+pub(crate) fn focus_camera(
+	actions: Res<ActionState>,
+	windows: Res<Windows>,
+	inspector: Res<PidInspector>,
+	cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+	towers: Query<(Entity, &GlobalTransform), With<Tower>>,
+	enemies: Query<(Entity, &GlobalTransform), With<Enemy>>,
+	mut camera_commands: EventWriter<CameraCommand>,
+) {
+	if actions.just_pressed(Action::ResetCamera) {
+		camera_commands.send(CameraCommand::Reset);
+	}
+	let focus = actions.just_pressed(Action::FocusCamera);
+	let follow = actions.just_pressed(Action::FollowWithCamera);
+	if !focus && !follow {
+		return;
+	}
+
+	let ray = cameras.iter().next()
+		.and_then(|(camera, camera_transform)| camera::cursor_ray(&windows, camera, camera_transform));
+	let pick = |candidates: &mut dyn Iterator<Item = (Entity, &GlobalTransform)>| {
+		ray.and_then(|ray| pick_entity(&ray, candidates.map(|(entity, transform)| (entity, transform.translation))))
+	};
+	let enemy = pick(&mut enemies.iter());
+	if follow {
+		camera_commands.send(match enemy {
+			Some(enemy) => CameraCommand::Follow(enemy),
+			None => CameraCommand::StopFollowing,
+		});
+	} else if let Some(entity) = enemy.or_else(|| pick(&mut towers.iter())).or(inspector.selected) {
+		camera_commands.send(CameraCommand::Focus(entity));
+	}
+}
+
+/// The entity closest to the camera that is within [`PICK_RADIUS`] of the ray.
+fn pick_entity(ray: &Ray, candidates: impl Iterator<Item = (Entity, Vec3)>) -> Option<Entity> {
+	let mut best: Option<(f32, Entity)> = None;
+	for (entity, position) in candidates {
+		if ray.distance_to_point(position) > PICK_RADIUS {
+			continue;
+		}
+		let depth = ray.closest_distance_along(position);
+		if best.is_none_or(|(best_depth, _)| depth < best_depth) {
+			best = Some((depth, entity));
+		}
+	}
+	best.map(|(_, entity)| entity)
+}
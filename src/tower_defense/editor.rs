@@ -0,0 +1,369 @@
+use bevy::{prelude::*, render::camera::Camera3d};
+use bevy::asset::AssetServerSettings;
+
+use crate::camera::{self, Ray};
+use crate::input::{Action, ActionState};
+
+use super::grid::{GridDetour, MazeGrid};
+use super::level::{self, BuildZone, CurrentLevel, Level, LevelAssets, LevelPath, PathNodeMarker};
+use super::map;
+
+/// How close the cursor has to be to a node to pick it.
+const PICK_RADIUS: f32 = 0.3;
+/// Build zones painted smaller than this are treated as a click, which erases the zone under the cursor.
+const MIN_ZONE_SIZE: f32 = 0.25;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SelectedNode {
+	pub path_entity: Entity,
+	pub path_id: u64,
+	pub index: usize,
+}
+
+/// Lets designers edit the current level while the game is running.
+///
+/// - F2 ([`Action::ToggleEditor`]): toggle the editor
+/// - Left click: select and drag path nodes
+/// - Ctrl + left click: add a node after the selected one
+/// - Delete ([`Action::DeleteNode`]): remove the selected node
+/// - Shift + left drag: paint a build zone. Shift + click erases one.
+/// - Ctrl + S ([`Action::SaveLevel`]): save the level. The game keeps going, instead of restarting like it does when
+///   the file is changed elsewhere.
+#[derive(Debug, Default)]
+pub struct EditorState {
+	pub enabled: bool,
+	pub selected: Option<SelectedNode>,
+	dragging: bool,
+	/// Where the build zone that is being painted started, and the entity previewing it.
+	painting: Option<(Vec3, Entity)>,
+}
+
+#[derive(Component)]
+pub(crate) struct EditorHelpText;
+
+pub(crate) fn setup_editor_ui(mut commands: Commands, asset_server: Res<AssetServer>) {
+	commands
+		.spawn_bundle(TextBundle {
+			style: Style {
+				position_type: PositionType::Absolute,
+				position: Rect {
+					right: Val::Px(10.0),
+					top: Val::Px(10.0),
+					..Default::default()
+				},
+				..Default::default()
+			},
+			text: Text::with_section(
+				"EDITOR\nClick: select/drag node\nCtrl+Click: add node\nDelete: remove node\nShift+Drag: paint build zone\nShift+Click: erase build zone\nCtrl+S: save",
+				TextStyle {
+					font: asset_server.load("fonts/Hack-Regular.ttf"),
+					font_size: 20.0,
+					color: Color::YELLOW,
+				},
+				Default::default(),
+			),
+			visibility: Visibility { is_visible: false },
+			..Default::default()
+		})
+		.insert(EditorHelpText);
+}
+
+/// Toggle the editor with [`Action::ToggleEditor`].
+pub(crate) fn toggle_editor(
+	actions: Res<ActionState>,
+	mut editor: ResMut<EditorState>,
+	mut help_texts: Query<&mut Visibility, With<EditorHelpText>>,
+) {
+	if !actions.just_pressed(Action::ToggleEditor) {
+		return;
+	}
+	editor.enabled = !editor.enabled;
+	editor.selected = None;
+	editor.dragging = false;
+	info!("Editor: {}", editor.enabled);
+	for mut visibility in help_texts.iter_mut() {
+		visibility.is_visible = editor.enabled;
+	}
+}
+
+fn modifier_pressed(keyboard_input: &Input<KeyCode>, left: KeyCode, right: KeyCode) -> bool {
+	keyboard_input.pressed(left) || keyboard_input.pressed(right)
+}
+
+fn primary_cursor_ray(
+	windows: &Windows,
+	cameras: &Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+) -> Option<Ray> {
+	let (camera, camera_transform) = cameras.iter().next()?;
+	camera::cursor_ray(windows, camera, camera_transform)
+}
+
+/// Select, move, add and remove path nodes.
+pub(crate) fn edit_paths(
+	mut editor: ResMut<EditorState>,
+	windows: Res<Windows>,
+	keyboard_input: Res<Input<KeyCode>>,
+	mouse_input: Res<Input<MouseButton>>,
+	actions: Res<ActionState>,
+	cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+	mut paths: Query<(Entity, &mut map::Path)>,
+) {
+	if !editor.enabled || modifier_pressed(&keyboard_input, KeyCode::LShift, KeyCode::RShift) {
+		return;
+	}
+	let ctrl = modifier_pressed(&keyboard_input, KeyCode::LControl, KeyCode::RControl);
+	let ray = primary_cursor_ray(&windows, &cameras);
+	let mut topology_changed = false;
+
+	if mouse_input.just_pressed(MouseButton::Left) {
+		if let Some(ray) = ray {
+			if ctrl {
+				if let Some(selected) = editor.selected {
+					if let Ok((_, mut path)) = paths.get_mut(selected.path_entity) {
+						let anchor = path.node_point(selected.index);
+						let normal = (anchor - ray.origin).normalize_or_zero();
+						if let Some(point) = ray.intersect_plane(anchor, normal) {
+							path.insert_point(selected.index + 1, point);
+							editor.selected = Some(SelectedNode { index: selected.index + 1, ..selected });
+							editor.dragging = true;
+							topology_changed = true;
+						}
+					}
+				}
+			} else {
+				editor.selected = pick_node(&ray, paths.iter());
+				editor.dragging = editor.selected.is_some();
+			}
+		}
+	}
+
+	if editor.dragging && mouse_input.pressed(MouseButton::Left) {
+		if let (Some(selected), Some(ray)) = (editor.selected, ray) {
+			if let Ok((_, mut path)) = paths.get_mut(selected.path_entity) {
+				let node = path.node_point(selected.index);
+				let normal = (node - ray.origin).normalize_or_zero();
+				if let Some(point) = ray.intersect_plane(node, normal) {
+					if point != node {
+						path.set_point(selected.index, point);
+					}
+				}
+			}
+		}
+	}
+	if mouse_input.just_released(MouseButton::Left) && editor.dragging {
+		editor.dragging = false;
+		topology_changed = true;
+	}
+
+	if actions.just_pressed(Action::DeleteNode) {
+		if let Some(selected) = editor.selected {
+			if let Ok((_, mut path)) = paths.get_mut(selected.path_entity) {
+				if path.remove_point(selected.index) {
+					editor.selected = Some(SelectedNode { index: selected.index.saturating_sub(1), ..selected });
+					topology_changed = true;
+				} else {
+					warn!("Path {} needs at least 2 nodes", selected.path_id);
+				}
+			}
+		}
+	}
+
+	if topology_changed {
+		let mut all_paths: Vec<&mut map::Path> = paths.iter_mut().map(|(_, path)| path.into_inner()).collect();
+		map::update_exit_distances(&mut all_paths);
+	}
+}
+
+/// The node closest to the camera that is within [`PICK_RADIUS`] of the ray.
+fn pick_node<'a>(ray: &Ray, paths: impl Iterator<Item = (Entity, &'a map::Path)>) -> Option<SelectedNode> {
+	let mut best: Option<(f32, SelectedNode)> = None;
+	for (entity, path) in paths {
+		for (index, point) in path.points().enumerate() {
+			if ray.distance_to_point(point) > PICK_RADIUS {
+				continue;
+			}
+			let depth = ray.closest_distance_along(point);
+			if best.is_none_or(|(best_depth, _)| depth < best_depth) {
+				best = Some((depth, SelectedNode { path_entity: entity, path_id: path.id, index }));
+			}
+		}
+	}
+	best.map(|(_, selected)| selected)
+}
+
+/// Make the selected node stand out.
+pub(crate) fn highlight_selected_node(
+	editor: Res<EditorState>,
+	mut markers: Query<(&PathNodeMarker, &mut Transform)>,
+) {
+	for (marker, mut transform) in markers.iter_mut() {
+		let selected = editor.enabled && editor.selected.is_some_and(|selected| {
+			selected.path_id == marker.path_id && selected.index == marker.index
+		});
+		transform.scale = Vec3::splat(if selected { 1.5 } else { 1. });
+	}
+}
+
+/// Paint build zones on the ground plane by dragging with Shift held.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn paint_build_zones(
+	mut commands: Commands,
+	mut editor: ResMut<EditorState>,
+	windows: Res<Windows>,
+	keyboard_input: Res<Input<KeyCode>>,
+	mouse_input: Res<Input<MouseButton>>,
+	level_assets: Res<LevelAssets>,
+	cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+	mut zones: Query<(Entity, &mut BuildZone, &mut Transform)>,
+) {
+	if !editor.enabled {
+		return;
+	}
+	let cursor = primary_cursor_ray(&windows, &cameras)
+		.and_then(|ray| ray.intersect_plane(Vec3::ZERO, Vec3::Z));
+
+	if mouse_input.just_pressed(MouseButton::Left) && modifier_pressed(&keyboard_input, KeyCode::LShift, KeyCode::RShift) {
+		if let Some(start) = cursor {
+			let entity = level::spawn_build_zone(&mut commands, zone_between(start, start), &level_assets);
+			editor.painting = Some((start, entity));
+		}
+	}
+
+	let (start, entity) = match editor.painting {
+		Some(painting) => painting,
+		None => return,
+	};
+	let end = cursor.unwrap_or(start);
+	if let Ok((_, mut zone, mut transform)) = zones.get_mut(entity) {
+		*zone = zone_between(start, end);
+		*transform = level::build_zone_transform(&zone);
+	}
+
+	if mouse_input.just_released(MouseButton::Left) {
+		editor.painting = None;
+		let size = (end - start).abs();
+		if size.x < MIN_ZONE_SIZE || size.y < MIN_ZONE_SIZE {
+			commands.entity(entity).despawn();
+			for (other, zone, _) in zones.iter() {
+				if other != entity && zone.contains(start) {
+					commands.entity(other).despawn();
+				}
+			}
+		}
+	}
+}
+
+fn zone_between(a: Vec3, b: Vec3) -> BuildZone {
+	BuildZone {
+		min: a.min(b).truncate().extend(-1.),
+		max: a.max(b).truncate().extend(1.),
+	}
+}
+
+/// `level` with the edited paths and build zones. The paths that the grid builds for its spawns aren't part of the
+/// level's file, since the grid builds them again when the level is loaded.
+fn edited_level<'a>(
+	level: &Level,
+	paths: impl Iterator<Item = &'a map::Path>,
+	zones: impl Iterator<Item = BuildZone>,
+	grid: Option<&MazeGrid>,
+) -> Level {
+	let generated = |id: u64| grid.is_some_and(|grid| grid.spawns.iter().any(|spawn| spawn.path_id == id));
+	let mut level = level.clone();
+	level.paths = paths
+		.filter(|path| !generated(path.id))
+		.map(LevelPath::from_path)
+		.collect();
+	level.paths.sort_by_key(|path| path.id);
+	level.build_zones = zones.collect();
+	level
+}
+
+/// Write the edited paths and build zones back to the level's file with [`Action::SaveLevel`].
+/// Levels with problems aren't saved, since they couldn't be loaded again.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn save_level(
+	editor: Res<EditorState>,
+	actions: Res<ActionState>,
+	mut current_level: ResMut<CurrentLevel>,
+	levels: Res<Assets<Level>>,
+	asset_settings: Option<Res<AssetServerSettings>>,
+	grid: Option<Res<MazeGrid>>,
+	paths: Query<&map::Path, Without<GridDetour>>,
+	zones: Query<&BuildZone>,
+) {
+	if !editor.enabled || !actions.just_pressed(Action::SaveLevel) {
+		return;
+	}
+	let level = match levels.get(&current_level.handle) {
+		Some(level) => edited_level(level, paths.iter(), zones.iter().copied(), grid.as_deref()),
+		None => {
+			warn!("Level {} is not loaded, nothing to save", current_level.name);
+			return;
+		}
+	};
+	let problems = level.validate();
+	if !problems.is_empty() {
+		for problem in problems {
+			error!("Level {}: {}", current_level.name, problem);
+		}
+		warn!("Not saving level {}, fix the problems above", current_level.name);
+		return;
+	}
+
+	let file = level::asset_file_path(asset_settings.as_deref(), &current_level.asset_path());
+	let result = level.to_ron()
+		.and_then(|serialized| std::fs::write(&file, &serialized).map(|_| serialized).map_err(anyhow::Error::from));
+	match result {
+		Ok(serialized) => {
+			info!("Saved level {} to {}", current_level.name, file.display());
+			current_level.editor_save = Some(serialized);
+		}
+		Err(err) => error!("Failed to save level {}: {}", current_level.name, err),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_pick_node() {
+		let path = map::Path::new(0, vec![Vec3::ZERO, Vec3::new(0., 0., 5.), Vec3::X]);
+		let ray = Ray {
+			origin: Vec3::new(0., 0., 10.),
+			direction: -Vec3::Z,
+		};
+		let picked = pick_node(&ray, std::iter::once((Entity::from_raw(0), &path))).unwrap();
+		assert_eq!(picked.index, 1);
+
+		let ray = Ray {
+			origin: Vec3::new(3., 0., 10.),
+			direction: -Vec3::Z,
+		};
+		assert_eq!(pick_node(&ray, std::iter::once((Entity::from_raw(0), &path))), None);
+	}
+
+	#[test]
+	fn test_save_grid_level() {
+		let level: Level = ron::de::from_str(include_str!("../../assets/levels/maze.level.ron")).unwrap();
+		let mut grid = level.grid.as_ref().unwrap().to_grid(&level.towers);
+		let mut paths: Vec<map::Path> = level.paths.iter().map(LevelPath::to_path).collect();
+		paths.extend(grid.build_paths());
+		let detour = grid.build_detour(grid.cell_center(grid.spawns[0].cell)).unwrap();
+
+		let saved = edited_level(&level, paths.iter(), level.build_zones.iter().copied(), Some(&grid));
+		assert_eq!(saved.validate(), Vec::<String>::new());
+		assert_eq!(saved.paths.len(), level.paths.len());
+		// detours are left out by the query in `save_level`, but would be caught if they weren't
+		let with_detour = edited_level(&level, paths.iter().chain([&detour]), std::iter::empty(), Some(&grid));
+		assert!(!with_detour.validate().is_empty());
+	}
+
+	#[test]
+	fn test_zone_between() {
+		let zone = zone_between(Vec3::new(2., -1., 0.), Vec3::new(-1., 3., 0.));
+		assert_eq!(zone.min, Vec3::new(-1., -1., -1.));
+		assert_eq!(zone.max, Vec3::new(2., 3., 1.));
+	}
+}
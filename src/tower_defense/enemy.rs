@@ -1,16 +1,127 @@
 use bevy::{prelude::*, ecs::event::Events};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
-use crate::{tower_defense::map, pid_controller::{PidControlledPosition, self, PidControlled}};
+use crate::{tower_defense::map, pid_controller::{PidCascade, PidControlled}};
 
+use super::motion::TrackedVelocity;
 use super::player::Player;
 
-pub const PID_CONTROL_POSITION: u64 = 0;
-
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct EnemyCreateOptions {
 	pub health: u32,
 	pub speed: f32,
 	pub path_id: u64,
+	#[serde(default)]
+	pub archetype: EnemyArchetype,
+	#[serde(default)]
+	pub movement: EnemyMovement,
+}
+
+/// How an enemy gets from its spawn to an exit.
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EnemyMovement {
+	/// Follows the paths on the ground.
+	#[default]
+	Ground,
+	/// Flies in a straight line from the start of its spawn path to the exit it would have walked to.
+	Flying,
+	/// Follows the paths on the ground, but steers towards its place on the path instead of being put right on it,
+	/// so it has to speed up and slow down, and swings wide around corners.
+	Steered,
+}
+
+/// Marks the [`PidCascade`] that moves a [`EnemyMovement::Steered`] enemy.
+#[derive(Debug)]
+pub struct Steering;
+
+/// How much faster than their speed steered enemies may go to catch up with their place on the path.
+const STEERING_CATCH_UP: f32 = 1.5;
+/// Most acceleration of steered enemies, in units per second squared.
+const STEERING_ACCELERATION: f32 = 10.;
+/// The place on the path of a steered enemy waits for it when it falls more than this many seconds of travel behind.
+const STEERING_LEASH: f32 = 1.;
+
+/// How far above the paths flying enemies fly.
+pub const FLIGHT_ALTITUDE: f32 = 3.;
+/// Stops flying enemies from looking for their exit forever if the paths loop.
+const MAX_BRANCHES_TO_EXIT: usize = 64;
+
+/// Determines what an enemy looks like. Enemies of the same archetype share their assets.
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EnemyArchetype {
+	#[default]
+	Basic,
+	/// Small enemies that come in large numbers.
+	Swarm,
+}
+
+/// How many tints enemies go through as they lose health.
+const HEALTH_TINT_STEPS: usize = 16;
+
+/// Mesh and material handles shared by every enemy of an archetype.
+#[derive(Debug, Clone)]
+pub struct EnemyPrefab {
+	pub mesh: Handle<Mesh>,
+	/// Materials tinted from red at no health to the archetype's color at full health.
+	/// Enemies swap between these handles instead of mutating a shared material.
+	health_materials: Vec<Handle<StandardMaterial>>,
+}
+
+impl EnemyPrefab {
+	fn new(mesh: Handle<Mesh>, color: Color, materials: &mut Assets<StandardMaterial>) -> Self {
+		let health_materials = (0..HEALTH_TINT_STEPS)
+			.map(|i| {
+				let t = i as f32 / (HEALTH_TINT_STEPS - 1) as f32;
+				materials.add(StandardMaterial {
+					base_color: Color::from(Vec4::from(Color::RED).lerp(Vec4::from(color), t)),
+					..Default::default()
+				})
+			})
+			.collect();
+		Self {
+			mesh,
+			health_materials,
+		}
+	}
+
+	/// The material to use for an enemy with the given amount of health remaining.
+	pub fn material(&self, health_percent: f32) -> &Handle<StandardMaterial> {
+		&self.health_materials[health_tint_index(health_percent)]
+	}
+}
+
+fn health_tint_index(health_percent: f32) -> usize {
+	(health_percent.clamp(0., 1.) * (HEALTH_TINT_STEPS - 1) as f32).round() as usize
+}
+
+/// Cached assets for each [`EnemyArchetype`], so that spawning an enemy doesn't create new assets.
+#[derive(Debug, Clone)]
+pub struct EnemyPrefabs {
+	basic: EnemyPrefab,
+	swarm: EnemyPrefab,
+}
+
+impl EnemyPrefabs {
+	pub fn new(meshes: &mut Assets<Mesh>, materials: &mut Assets<StandardMaterial>) -> Self {
+		Self {
+			basic: EnemyPrefab::new(meshes.add(Mesh::from(shape::Cube { size: 1.0 })), Color::PINK, materials),
+			swarm: EnemyPrefab::new(meshes.add(Mesh::from(shape::Cube { size: 0.5 })), Color::PURPLE, materials),
+		}
+	}
+
+	pub fn get(&self, archetype: EnemyArchetype) -> &EnemyPrefab {
+		match archetype {
+			EnemyArchetype::Basic => &self.basic,
+			EnemyArchetype::Swarm => &self.swarm,
+		}
+	}
+}
+
+/// Request for an enemy to be spawned. Handled by [`spawn_enemies`].
+#[derive(Debug, Clone, Copy)]
+pub struct EventSpawnEnemy {
+	pub options: EnemyCreateOptions,
 }
 
 #[derive(Component, Debug)]
@@ -19,8 +130,17 @@ pub struct Enemy {
 	pub max_health: u32,
 	pub path_id: u64,
 	pub path_pos: f32,
+	/// The branch that the enemy will take once it reaches the end of its current path.
+	/// Chosen ahead of time so that the enemy's movement can be predicted.
+	pub next_path_id: Option<u64>,
+	/// Shortest distance left to travel before the enemy reaches an exit.
+	pub distance_to_exit: f32,
 	/// Speed that the enemy travels in units per second.
 	pub speed: f32,
+	pub archetype: EnemyArchetype,
+	pub movement: EnemyMovement,
+	/// Where flying enemies are flying to.
+	pub destination: Vec3,
 }
 
 impl Enemy {
@@ -30,14 +150,26 @@ impl Enemy {
 			max_health: options.health,
 			path_id: options.path_id,
 			path_pos: 0.,
+			next_path_id: None,
+			distance_to_exit: f32::INFINITY,
 			speed: options.speed,
+			archetype: options.archetype,
+			movement: options.movement,
+			destination: Vec3::ZERO,
 		}
 	}
 
 	/// Hurt the enemy for the given amount of damage.
 	///
 	/// ```
-	/// let mut enemy = Enemy { health: 10 };
+	/// # use bevy_playground::tower_defense::enemy::{Enemy, EnemyCreateOptions};
+	/// let mut enemy = Enemy::new(EnemyCreateOptions {
+	///     health: 10,
+	///     speed: 1.,
+	///     path_id: 0,
+	///     archetype: Default::default(),
+	///     movement: Default::default(),
+	/// });
 	/// enemy.hurt(5);
 	/// assert_eq!(enemy.health, 5);
 	/// enemy.hurt(20);
@@ -51,35 +183,88 @@ impl Enemy {
 		}
 	}
 
-	/// Spawns the enemy in the world.
-	pub fn spawn(
-		self,
-		mut commands: Commands,
-		mut meshes: ResMut<Assets<Mesh>>,
-		mut materials: ResMut<Assets<StandardMaterial>>,
-	) {
-		let mesh = meshes.add(Mesh::from(shape::Cube { size: 1.0 }));
-		let material = materials.add(StandardMaterial {
-			base_color: Color::PINK,
-			..Default::default()
-		});
-		commands.spawn_bundle(
+	/// Spawns the enemy in the world at `position`, using the shared assets from `prefab`.
+	pub fn spawn(self, commands: &mut Commands, prefab: &EnemyPrefab, position: Vec3) {
+		let mut entity = commands.spawn_bundle(
 			PbrBundle {
-				mesh,
-				material,
-				transform: Transform::from_xyz(-10000., 10000., 0.),
+				mesh: prefab.mesh.clone(),
+				material: prefab.material(self.health_percent()).clone(),
+				transform: Transform::from_translation(position),
 				..Default::default()
 			}
-		)
-			// .insert(PidControlled::<Vec3, PID_CONTROL_POSITION>::new(1., 1., 1.))
+		);
+		if self.movement == EnemyMovement::Steered {
+			let mut steering = self.steering();
+			steering.set_target(position);
+			entity.insert(steering);
+		}
+		entity
+			.insert(TrackedVelocity::default())
 			.insert(self);
 	}
 
+	/// Controller for a [`EnemyMovement::Steered`] enemy, that moves it no faster than it can catch up with its place on the path.
+	pub fn steering(&self) -> PidCascade<Vec3, Steering> {
+		PidCascade::new(
+			PidControlled::new(4., 0., 0.).with_output_limit(self.speed * STEERING_CATCH_UP),
+			PidControlled::new(8., 0., 0.).with_output_limit(STEERING_ACCELERATION),
+		)
+	}
+
+	/// Moves the enemy `distance` further along its path, continuing on to the chosen branch when reaching the end of it.
+	/// Returns the path that the enemy ends up on, or `None` if its path doesn't exist (yet), in which case it stays put.
+	fn advance_along_path<'a>(&mut self, paths: &'a map::Paths, distance: f32, rng: &mut impl Rng) -> Option<&'a map::Path> {
+		let mut path = paths.get(self.path_id)?;
+		self.path_pos += distance;
+		while self.path_pos > path.total_length() {
+			let next_path = match self.next_path_id.and_then(|id| paths.get(id)) {
+				Some(next_path) => next_path,
+				None => break,
+			};
+			self.path_pos -= path.total_length();
+			self.path_id = next_path.id;
+			self.next_path_id = next_path.choose_branch(rng.gen());
+			path = next_path;
+		}
+		self.distance_to_exit = path.distance_to_exit(self.path_pos);
+		Some(path)
+	}
+
 	pub fn health_percent(&self) -> f32 {
 		self.health as f32 / self.max_health as f32
 	}
+
+	pub fn is_flying(&self) -> bool {
+		self.movement == EnemyMovement::Flying
+	}
+
+	/// Where a flying enemy at `position` will be after flying towards its destination for `time` seconds.
+	fn position_towards_destination(&self, position: Vec3, time: f32) -> Vec3 {
+		let offset = self.destination - position;
+		if offset.length() <= self.speed * time {
+			return self.destination;
+		}
+		position + offset.normalize() * self.speed * time
+	}
+}
+
+/// Accelerates a steered enemy at `position` moving at `velocity` towards `target` for `delta` seconds,
+/// and returns its new velocity.
+fn steer(steering: &mut PidCascade<Vec3, Steering>, position: Vec3, velocity: Vec3, target: Vec3, delta: f32) -> Vec3 {
+	steering.set_target(target);
+	velocity + steering.compute(delta, position, velocity) * delta
 }
 
+/// Turn `transform` to face `direction`.
+fn face(transform: &mut Transform, direction: Vec3) {
+	if direction != Vec3::ZERO {
+		let up = if direction.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+		let target = transform.translation + direction;
+		transform.look_at(target, up);
+	}
+}
+
+
 #[test]
 fn test_enemy_hurt() {
 	// TODO: move to doctest for hurt
@@ -87,6 +272,8 @@ fn test_enemy_hurt() {
 		health: 10,
 		speed: 1.,
 		path_id: 0,
+		archetype: EnemyArchetype::Basic,
+		movement: EnemyMovement::Ground,
 	});
 	enemy.hurt(5);
 	assert_eq!(enemy.health, 5);
@@ -94,46 +281,168 @@ fn test_enemy_hurt() {
 	assert_eq!(enemy.health, 0);
 }
 
-#[allow(dead_code)]
-pub(crate) fn move_enemies(
+#[test]
+fn test_steering_bounded_velocity() {
+	let enemy = Enemy::new(EnemyCreateOptions {
+		health: 10,
+		speed: 2.,
+		path_id: 0,
+		archetype: EnemyArchetype::Basic,
+		movement: EnemyMovement::Steered,
+	});
+	let mut steering = enemy.steering();
+	let (mut position, mut velocity) = (Vec3::ZERO, Vec3::ZERO);
+	let target = Vec3::new(20., 0., 0.);
+	let delta = 1. / 60.;
+	// starts from standing still instead of jumping to the target
+	velocity = steer(&mut steering, position, velocity, target, delta);
+	assert!(velocity.length() <= STEERING_ACCELERATION * delta + 0.0001);
+	for _ in 0..20 * 60 {
+		position += velocity * delta;
+		velocity = steer(&mut steering, position, velocity, target, delta);
+		assert!(velocity.length() <= enemy.speed * STEERING_CATCH_UP * 1.05, "{}", velocity);
+	}
+	assert!(position.distance(target) < 0.01, "{}", position);
+}
+
+#[test]
+fn test_flying_towards_destination() {
+	let mut enemy = Enemy::new(EnemyCreateOptions {
+		health: 10,
+		speed: 2.,
+		path_id: 0,
+		archetype: EnemyArchetype::Basic,
+		movement: EnemyMovement::Flying,
+	});
+	enemy.destination = Vec3::new(10., 0., 0.);
+	let predict = |time| enemy.position_towards_destination(Vec3::ZERO, time);
+	assert_eq!(predict(1.), Vec3::new(2., 0., 0.));
+	// doesn't overshoot the destination
+	assert_eq!(predict(100.), Vec3::new(10., 0., 0.));
+}
+
+#[test]
+fn test_health_tint_index() {
+	assert_eq!(health_tint_index(0.), 0);
+	assert_eq!(health_tint_index(1.), HEALTH_TINT_STEPS - 1);
+	assert_eq!(health_tint_index(1.5), HEALTH_TINT_STEPS - 1);
+	assert!(health_tint_index(0.25) < health_tint_index(0.75));
+}
+
+pub(crate) fn add_enemy_prefabs(
+	mut commands: Commands,
+	mut meshes: ResMut<Assets<Mesh>>,
+	mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+	commands.insert_resource(EnemyPrefabs::new(&mut meshes, &mut materials));
+}
+
+pub(crate) fn spawn_enemies(
+	mut commands: Commands,
+	prefabs: Res<EnemyPrefabs>,
+	mut spawn_requests: ResMut<Events<EventSpawnEnemy>>,
+	paths: map::Paths,
+) {
+	let mut rng = rand::thread_rng();
+	for event in spawn_requests.drain() {
+		let mut enemy = Enemy::new(event.options);
+		let path = match paths.get(enemy.path_id) {
+			Some(path) => path,
+			None => {
+				error!("Enemy spawned on a path that does not exist: {}", enemy.path_id);
+				continue;
+			}
+		};
+		let mut position = path.get_point_along_path(0.);
+		enemy.next_path_id = path.choose_branch(rng.gen());
+		enemy.distance_to_exit = path.distance_to_exit(0.);
+		if enemy.is_flying() {
+			// fly straight to wherever the chosen branches would have led
+			let mut exit = path;
+			let mut next_path_id = enemy.next_path_id;
+			for _ in 0..MAX_BRANCHES_TO_EXIT {
+				match next_path_id.and_then(|id| paths.get(id)) {
+					Some(next_path) => {
+						exit = next_path;
+						next_path_id = next_path.choose_branch(rng.gen());
+					}
+					None => break,
+				}
+			}
+			position += Vec3::Z * FLIGHT_ALTITUDE;
+			enemy.destination = exit.get_point_along_path(exit.total_length()) + Vec3::Z * FLIGHT_ALTITUDE;
+			enemy.next_path_id = None;
+			enemy.distance_to_exit = position.distance(enemy.destination);
+		}
+		enemy.spawn(&mut commands, prefabs.get(event.options.archetype), position);
+	}
+}
+
+pub fn move_enemies(
 	time: Res<Time>,
 	mut query: Query<(&mut Enemy, &mut Transform), With<Enemy>>,
-	path: Query<&map::Path>,
+	paths: map::Paths,
 ) {
+	let mut rng = rand::thread_rng();
+	let delta = time.delta_seconds();
 	for enemy in query.iter_mut() {
 		let (mut enemy, mut transform) = enemy;
-		let path = path.iter()
-			.find(|path| path.id == enemy.path_id)
-			.expect(format!("No path with id: {}", enemy.path_id).as_str());
-		enemy.path_pos += enemy.speed * time.delta().as_secs_f32();
-		transform.translation = path.get_point_along_path(enemy.path_pos);
+		let direction = match enemy.movement {
+			EnemyMovement::Ground => {
+				let distance = enemy.speed * delta;
+				let path = match enemy.advance_along_path(&paths, distance, &mut rng) {
+					Some(path) => path,
+					None => continue,
+				};
+				transform.translation = path.get_point_along_path(enemy.path_pos);
+				path.get_direction_along_path(enemy.path_pos)
+			}
+			EnemyMovement::Flying => {
+				transform.translation = enemy.position_towards_destination(transform.translation, delta);
+				enemy.distance_to_exit = transform.translation.distance(enemy.destination);
+				(enemy.destination - transform.translation).normalize_or_zero()
+			}
+			// moved by `move_enemies_with_pid`
+			EnemyMovement::Steered => continue,
+		};
+		// face the direction of travel
+		face(&mut transform, direction);
 	}
 }
 
-#[allow(dead_code)]
+/// Moves [`EnemyMovement::Steered`] enemies towards their place on the path, which moves along it at their speed.
 pub(crate) fn move_enemies_with_pid(
 	time: Res<Time>,
-	mut query: Query<(&mut Enemy, &mut PidControlled<Vec3, PID_CONTROL_POSITION>), With<Enemy>>,
-	path: Query<&map::Path>,
+	mut query: Query<(&mut Enemy, &mut PidCascade<Vec3, Steering>, &TrackedVelocity, &mut Transform)>,
+	paths: map::Paths,
 ) {
-	for enemy in query.iter_mut() {
-		let (mut enemy, mut transform) = enemy;
-		let path = path.iter()
-			.find(|path| path.id == enemy.path_id)
-			.expect(format!("No path with id: {}", enemy.path_id).as_str());
-		enemy.path_pos += enemy.speed * time.delta().as_secs_f32();
-		transform.set_target(path.get_point_along_path(enemy.path_pos));
+	let mut rng = rand::thread_rng();
+	let delta = time.delta_seconds();
+	if delta <= 0. {
+		return;
+	}
+	for (mut enemy, mut steering, tracked, mut transform) in query.iter_mut() {
+		let behind = transform.translation.distance(steering.target()) > enemy.speed * STEERING_LEASH;
+		let distance = if behind { 0. } else { enemy.speed * delta };
+		let path = match enemy.advance_along_path(&paths, distance, &mut rng) {
+			Some(path) => path,
+			None => continue,
+		};
+		let target = path.get_point_along_path(enemy.path_pos);
+		let velocity = steer(&mut steering, transform.translation, tracked.velocity, target, delta);
+		transform.translation += velocity * delta;
+		face(&mut transform, velocity.normalize_or_zero());
 	}
 }
 
 pub(crate) fn monitor_health(
 	time: Res<Time>,
+	prefabs: Res<EnemyPrefabs>,
 	mut enemy_deaths: ResMut<Events<EventEnemyDeath>>,
 	mut commands: Commands,
-	mut query: Query<(Entity, &Enemy, &Handle<StandardMaterial>, &mut Transform), With<Enemy>>,
-	mut materials: ResMut<Assets<StandardMaterial>>
+	mut query: Query<(Entity, &Enemy, &mut Handle<StandardMaterial>, &mut Transform), With<Enemy>>,
 ) {
-	for (entity, enemy, material_handle, mut transform) in query.iter_mut() {
+	for (entity, enemy, mut material_handle, mut transform) in query.iter_mut() {
 		if enemy.health <= 0 {
 			commands.entity(entity).despawn();
 			enemy_deaths.send(EventEnemyDeath {
@@ -141,17 +450,47 @@ pub(crate) fn monitor_health(
 			});
 			continue;
 		}
-		let mat = materials.get_mut(material_handle).expect("no material found");
-		// FIXME: changes color for all enemies that share this material instead of just this one. maybe I have to do some shader stuff?
-		let target_color = Color::from(Vec4::from(Color::RED).lerp(Vec4::from(Color::WHITE), enemy.health_percent()));
+		let material = prefabs.get(enemy.archetype).material(enemy.health_percent());
+		if *material_handle != *material {
+			*material_handle = material.clone();
+		}
 		let target_scale = Vec3::new(0.5, 0.5, 0.5).lerp(Vec3::ONE, enemy.health_percent());
 		let lerp_speed = 5.;
 		let lerp_amount = (time.delta_seconds() * lerp_speed).clamp(0., 1.);
-		mat.base_color = Color::from(Vec4::from(mat.base_color).lerp(Vec4::from(target_color), lerp_amount));
 		transform.scale = transform.scale.lerp(target_scale, lerp_amount);
 	}
 }
 
+/// Sent whenever an enemy takes damage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EventEnemyHurt {
+	pub enemy: Entity,
+	pub damage: u32,
+	/// Where the enemy was when it got hit.
+	pub position: Vec3,
+}
+
+/// Enemies that reach the end of a path with nowhere left to go escape, and cost the player a life.
+pub(crate) fn process_enemy_escapes(
+	mut commands: Commands,
+	mut player: Query<&mut Player>,
+	enemies: Query<(Entity, &Enemy)>,
+) {
+	let mut player = player.single_mut();
+	for (entity, enemy) in enemies.iter() {
+		if enemy.health > 0 && enemy.next_path_id.is_none() && enemy.distance_to_exit <= 0. {
+			commands.entity(entity).despawn();
+			if player.lives() > 0 {
+				player.lose_life();
+				debug!("Enemy escaped, {} lives left", player.lives());
+				if player.lives() == 0 {
+					info!("Out of lives!");
+				}
+			}
+		}
+	}
+}
+
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub struct EventEnemyDeath {
 	pub enemy: Entity,
@@ -1,33 +1,151 @@
-use bevy::{prelude::*, ecs::event::Events};
+use bevy::{prelude::*, ecs::event::{Events, ManualEventReader}};
+
+/// How much experience it takes in total to reach each level. Every level has to take more than the one before.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LevelCurve {
+	/// The experience needed for level 1, 2, 3 and so on. Levels past the end of the table can't be reached.
+	Table(Vec<u64>),
+	/// `per_level` experience for every level.
+	Linear { per_level: u64 },
+	/// `scale * level^exponent`.
+	Polynomial { scale: f64, exponent: f64 },
+	/// `first` for level 1, and `growth` times as much for every level after that.
+	Exponential { first: u64, growth: f64 },
+}
+
+impl Default for LevelCurve {
+	/// Doubles with every level: 2, 4, 8, 16, ...
+	fn default() -> Self {
+		Self::Exponential { first: 2, growth: 2. }
+	}
+}
+
+impl LevelCurve {
+	/// The total experience needed to reach `level`, or `None` if it can't be reached.
+	pub fn experience_for_level(&self, level: u64) -> Option<u64> {
+		if level == 0 {
+			return Some(0);
+		}
+		match self {
+			Self::Table(table) => usize::try_from(level - 1).ok().and_then(|i| table.get(i)).copied(),
+			Self::Linear { per_level } => per_level.checked_mul(level),
+			Self::Polynomial { scale, exponent } => float_experience(scale * (level as f64).powf(*exponent)),
+			Self::Exponential { first, growth } => float_experience(*first as f64 * growth.powf((level - 1) as f64)),
+		}
+	}
+}
+
+/// Rounds to whole experience, or `None` if it is too much to ever earn.
+fn float_experience(experience: f64) -> Option<u64> {
+	let experience = experience.round();
+	(experience.is_finite() && experience < u64::MAX as f64).then_some(experience.max(0.) as u64)
+}
 
 /// Defines what level this entity is, and manages the experience required to level up.
-#[derive(Component, Default)]
+#[derive(Component, Debug, Clone, Default)]
 pub struct ExpLevel {
 	experience: u64,
 	level: u64,
+	curve: LevelCurve,
+	max_level: Option<u64>,
 }
 
 impl ExpLevel {
 	pub fn new() -> Self { Default::default() }
 
+	pub fn with_curve(mut self, curve: LevelCurve) -> Self {
+		self.curve = curve;
+		self
+	}
+
+	pub fn with_max_level(mut self, max_level: u64) -> Self {
+		self.max_level = Some(max_level);
+		self
+	}
+
+	pub fn curve(&self) -> &LevelCurve {
+		&self.curve
+	}
+
+	pub fn max_level(&self) -> Option<u64> {
+		self.max_level
+	}
+
 	pub fn experience(&self) -> u64 {
 		self.experience
 	}
 
 	/// Add experience. Check [`need_level_up()`] for whether the entity is ready to level up.
 	pub fn add_experience(&mut self, experience: u64) {
-		self.experience += experience;
+		self.experience = self.experience.saturating_add(experience);
 	}
 
 	pub fn level(&self) -> u64 {
 		self.level
 	}
 
+	pub fn is_max_level(&self) -> bool {
+		self.level_after(self.level).is_none()
+	}
+
+	/// The next level, or `None` if there isn't one.
+	fn level_after(&self, level: u64) -> Option<u64> {
+		let next = level.checked_add(1)?;
+		if self.max_level.is_some_and(|max| next > max) {
+			return None;
+		}
+		// a curve that stops growing would never end
+		let needed = self.curve.experience_for_level(next)?;
+		(level == 0 || self.curve.experience_for_level(level).is_some_and(|current| needed > current)).then_some(next)
+	}
+
+	/// The total experience needed for the next level, or `None` at the max level.
+	pub fn experience_for_next_level(&self) -> Option<u64> {
+		self.level_after(self.level).and_then(|next| self.curve.experience_for_level(next))
+	}
+
+	/// How much more experience is needed for the next level, or `None` at the max level.
+	pub fn experience_to_next_level(&self) -> Option<u64> {
+		self.experience_for_next_level().map(|needed| needed.saturating_sub(self.experience))
+	}
+
+	/// How far along the way from the current level to the next one the experience is, from 0 to 1. Always 1 at the
+	/// max level. For progress bars.
+	pub fn progress(&self) -> f32 {
+		let (current, next) = match (self.curve.experience_for_level(self.level), self.experience_for_next_level()) {
+			(Some(current), Some(next)) => (current, next),
+			_ => return 1.,
+		};
+		(self.experience.saturating_sub(current) as f64 / (next - current) as f64).min(1.) as f32
+	}
+
 	/// Get what the tower's level should be based on the earned experience.
 	/// If this value is different than the current level, the tower is ready
 	/// to level up.
 	fn level_from_exp(&self) -> u64 {
-		(self.experience as f64).log2().ceil() as u64 // TODO: fine tune this formula, copilot made it
+		// a level only counts if it comes after the one before, so a curve that stops growing ends there like in
+		// `is_max_level()` instead of running off to u64::MAX
+		let reached = |level: u64| self.level_after(level - 1) == Some(level)
+			&& self.curve.experience_for_level(level).is_some_and(|needed| needed <= self.experience);
+		// levels only take more and more experience, so find one that isn't reached by doubling,
+		// then narrow down on the last one that is
+		let (mut low, mut high) = (0, 1);
+		while reached(high) {
+			if high == u64::MAX {
+				return high;
+			}
+			low = high;
+			high = high.saturating_mul(2);
+		}
+		while high - low > 1 {
+			let middle = low + (high - low) / 2;
+			if reached(middle) {
+				low = middle;
+			} else {
+				high = middle;
+			}
+		}
+		low
 	}
 
 	pub fn need_level_up(&self) -> bool {
@@ -45,9 +163,10 @@ pub fn process_level_ups(
 ) {
 	for (entity, mut obj) in objects.iter_mut() {
 		if obj.need_level_up() {
+			let old_level = obj.level();
 			obj.apply_level_up();
-			debug!("Object leveled up to {}.", obj.level());
-			expbus.level_up.send(EventLevelUp { entity });
+			debug!("Object leveled up from {} to {}.", old_level, obj.level());
+			expbus.level_up.send(EventLevelUp { entity, old_level, new_level: obj.level() });
 		}
 	}
 }
@@ -80,9 +199,10 @@ pub fn update_exp_bus(
 
 pub fn process_experience_gain(
 	expbus: Res<ExperienceBus>,
+	// kept between runs, so that events aren't counted again before the bus drops them
+	mut reader: Local<ManualEventReader<EventExpGain>>,
 	mut objs: Query<&mut ExpLevel>,
 ) {
-	let mut reader = expbus.experience_gain.get_reader();
 	for event in reader.iter(&expbus.experience_gain) {
 		let result = objs.get_mut(event.entity);
 		if let Ok(mut level) = result {
@@ -102,6 +222,9 @@ pub struct EventExpGain {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct EventLevelUp {
 	pub entity: Entity,
+	/// Can be more than one level below `new_level`, if a lot of experience was gained at once.
+	pub old_level: u64,
+	pub new_level: u64,
 }
 
 #[cfg(test)]
@@ -113,6 +236,7 @@ mod test {
 		let mut explevel = ExpLevel {
 			experience: 0,
 			level: 0,
+			..Default::default()
 		};
 		explevel.add_experience(10);
 		assert_eq!(explevel.experience(), 10);
@@ -125,6 +249,7 @@ mod test {
 		let explevel = ExpLevel {
 			experience: 100000,
 			level: 0,
+			..Default::default()
 		};
 		assert!(explevel.need_level_up());
 	}
@@ -134,9 +259,100 @@ mod test {
 		let mut explevel = ExpLevel {
 			experience: 1000,
 			level: 0,
+			..Default::default()
 		};
 		assert!(explevel.level_from_exp() > 0);
 		explevel.apply_level_up();
 		assert_eq!(explevel.level(), explevel.level_from_exp());
 	}
+
+	#[test]
+	fn test_curves() {
+		let levels = |curve: LevelCurve| (0..5).map(|level| curve.experience_for_level(level)).collect::<Vec<_>>();
+		assert_eq!(levels(LevelCurve::Table(vec![5, 20, 50])), [Some(0), Some(5), Some(20), Some(50), None]);
+		assert_eq!(levels(LevelCurve::Linear { per_level: 10 }), [Some(0), Some(10), Some(20), Some(30), Some(40)]);
+		assert_eq!(levels(LevelCurve::Polynomial { scale: 3., exponent: 2. }), [Some(0), Some(3), Some(12), Some(27), Some(48)]);
+		assert_eq!(levels(LevelCurve::default()), [Some(0), Some(2), Some(4), Some(8), Some(16)]);
+		assert_eq!(LevelCurve::default().experience_for_level(100), None);
+	}
+
+	#[test]
+	fn test_max_level() {
+		let mut explevel = ExpLevel::new().with_curve(LevelCurve::Linear { per_level: 10 }).with_max_level(3);
+		explevel.add_experience(1000);
+		explevel.apply_level_up();
+		assert_eq!(explevel.level(), 3);
+		assert!(explevel.is_max_level());
+		assert_eq!(explevel.experience_to_next_level(), None);
+		assert_eq!(explevel.progress(), 1.);
+
+		// the end of a table is a max level too
+		let mut explevel = ExpLevel::new().with_curve(LevelCurve::Table(vec![5, 20]));
+		explevel.add_experience(1000);
+		explevel.apply_level_up();
+		assert_eq!(explevel.level(), 2);
+		assert!(explevel.is_max_level());
+	}
+
+	#[test]
+	fn test_lots_of_experience() {
+		let mut explevel = ExpLevel::new().with_curve(LevelCurve::Linear { per_level: 1 });
+		explevel.add_experience(10_000_000_000);
+		explevel.apply_level_up();
+		assert_eq!(explevel.level(), 10_000_000_000);
+		assert_eq!(explevel.experience_to_next_level(), Some(1));
+
+		explevel.add_experience(u64::MAX);
+		explevel.apply_level_up();
+		assert_eq!(explevel.level(), u64::MAX);
+		assert!(explevel.is_max_level());
+
+		let mut explevel = ExpLevel::new().with_curve(LevelCurve::Polynomial { scale: 1., exponent: 2. }).with_max_level(1_000_000);
+		explevel.add_experience(u64::MAX);
+		explevel.apply_level_up();
+		assert_eq!(explevel.level(), 1_000_000);
+	}
+
+	#[test]
+	fn test_flat_curve() {
+		let mut explevel = ExpLevel::new().with_curve(LevelCurve::Linear { per_level: 0 });
+		explevel.add_experience(10);
+		explevel.apply_level_up();
+		assert_eq!(explevel.level(), 1);
+		assert!(explevel.is_max_level());
+		assert!(!explevel.need_level_up());
+
+		let mut explevel = ExpLevel::new().with_curve(LevelCurve::Table(vec![5, 20, 20]));
+		explevel.add_experience(1000);
+		explevel.apply_level_up();
+		assert_eq!(explevel.level(), 2);
+		assert!(explevel.is_max_level());
+	}
+
+	#[test]
+	fn test_experience_to_next_level() {
+		let mut explevel = ExpLevel::new().with_curve(LevelCurve::Table(vec![10, 30]));
+		assert_eq!(explevel.experience_to_next_level(), Some(10));
+		explevel.add_experience(15);
+		explevel.apply_level_up();
+		assert_eq!(explevel.level(), 1);
+		assert_eq!(explevel.experience_for_next_level(), Some(30));
+		assert_eq!(explevel.experience_to_next_level(), Some(15));
+		assert_eq!(explevel.progress(), 0.25);
+	}
+
+	#[test]
+	fn test_multi_level_up_event() {
+		let mut world = World::new();
+		world.insert_resource(ExperienceBus::new());
+		let entity = world.spawn().insert(ExpLevel::new().with_curve(LevelCurve::Linear { per_level: 10 })).id();
+		let mut stage = SystemStage::single_threaded().with_system(process_level_ups);
+		stage.run(&mut world);
+
+		world.get_mut::<ExpLevel>(entity).unwrap().add_experience(35);
+		stage.run(&mut world);
+		let expbus = world.resource::<ExperienceBus>();
+		let events: Vec<_> = ManualEventReader::default().iter(&expbus.level_up).copied().collect();
+		assert_eq!(events, [EventLevelUp { entity, old_level: 0, new_level: 3 }]);
+	}
 }
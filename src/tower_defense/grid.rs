@@ -0,0 +1,460 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use bevy::{prelude::*, render::camera::Camera3d, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::camera;
+use crate::input::{Action, ActionState};
+
+use super::editor::EditorState;
+use super::enemy::Enemy;
+use super::map;
+use super::player::Player;
+use super::towers::{self, TowerAssets, TowerAttackType};
+use super::level::LevelEntity;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GridCell {
+	Open,
+	/// Part of the level. Can't be walked through or built on.
+	Wall,
+	/// Has a tower on it.
+	Tower,
+}
+
+/// Ids from here on are given to detours. Levels should give their paths smaller ids.
+pub const FIRST_DETOUR_ID: u64 = 1 << 32;
+
+/// A cell where enemies enter the grid, and the id of the path that gets generated for them.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GridSpawn {
+	pub path_id: u64,
+	pub cell: UVec2,
+}
+
+/// A map where enemies find their own way from the spawns to the exit, and towers block their way.
+///
+/// Routes through the grid are turned into regular [`map::Path`]s, so enemies move along them like
+/// any other path. Towers may not be placed where they would cut a spawn off from the exit.
+#[derive(Debug, Clone)]
+pub struct MazeGrid {
+	/// World position of the center of cell (0, 0). The grid extends along +X and +Y.
+	pub origin: Vec3,
+	pub cell_size: f32,
+	width: u32,
+	height: u32,
+	cells: Vec<GridCell>,
+	pub spawns: Vec<GridSpawn>,
+	pub exit: UVec2,
+	next_detour_id: u64,
+}
+
+impl MazeGrid {
+	pub fn new(width: u32, height: u32, exit: UVec2) -> Self {
+		Self {
+			origin: Vec3::ZERO,
+			cell_size: 1.,
+			width,
+			height,
+			cells: vec![GridCell::Open; (width * height) as usize],
+			spawns: vec![],
+			exit,
+			next_detour_id: FIRST_DETOUR_ID,
+		}
+	}
+
+	pub fn with_origin(mut self, origin: Vec3, cell_size: f32) -> Self {
+		self.origin = origin;
+		self.cell_size = cell_size;
+		self
+	}
+
+	pub fn with_spawn(mut self, path_id: u64, cell: UVec2) -> Self {
+		self.spawns.push(GridSpawn { path_id, cell });
+		self
+	}
+
+	fn index(&self, cell: UVec2) -> Option<usize> {
+		if cell.x < self.width && cell.y < self.height {
+			Some((cell.y * self.width + cell.x) as usize)
+		} else {
+			None
+		}
+	}
+
+	/// Returns `None` for cells outside of the grid.
+	pub fn cell(&self, cell: UVec2) -> Option<GridCell> {
+		self.index(cell).map(|i| self.cells[i])
+	}
+
+	pub fn set_cell(&mut self, cell: UVec2, value: GridCell) {
+		if let Some(i) = self.index(cell) {
+			self.cells[i] = value;
+		}
+	}
+
+	pub fn is_walkable(&self, cell: UVec2) -> bool {
+		self.cell(cell) == Some(GridCell::Open)
+	}
+
+	/// The cell that contains the world position, if it is on the grid.
+	pub fn cell_at(&self, position: Vec3) -> Option<UVec2> {
+		let local = ((position - self.origin).truncate() / self.cell_size).round();
+		if local.x < 0. || local.y < 0. {
+			return None;
+		}
+		let cell = local.as_uvec2();
+		self.index(cell).map(|_| cell)
+	}
+
+	/// World position of the center of the cell.
+	pub fn cell_center(&self, cell: UVec2) -> Vec3 {
+		self.origin + (cell.as_vec2() * self.cell_size).extend(0.)
+	}
+
+	fn neighbors(&self, cell: UVec2) -> impl Iterator<Item = UVec2> + '_ {
+		let cell = cell.as_ivec2();
+		[IVec2::X, IVec2::Y, -IVec2::X, -IVec2::Y].into_iter()
+			.map(move |offset| cell + offset)
+			.filter(|neighbor| neighbor.x >= 0 && neighbor.y >= 0)
+			.map(|neighbor| neighbor.as_uvec2())
+			.filter(|neighbor| self.is_walkable(*neighbor))
+	}
+
+	/// Find the shortest route between two cells with A*, moving horizontally and vertically.
+	/// The route includes both `start` and `goal`.
+	pub fn find_route(&self, start: UVec2, goal: UVec2) -> Option<Vec<UVec2>> {
+		if !self.is_walkable(start) || !self.is_walkable(goal) {
+			return None;
+		}
+		// manhattan distance, since there are no diagonal moves
+		let heuristic = |cell: UVec2| {
+			let offset = (cell.as_ivec2() - goal.as_ivec2()).abs();
+			(offset.x + offset.y) as u32
+		};
+		let mut open = BinaryHeap::new();
+		let mut came_from: HashMap<UVec2, UVec2> = HashMap::default();
+		let mut costs: HashMap<UVec2, u32> = HashMap::default();
+		costs.insert(start, 0);
+		open.push(Reverse((heuristic(start), 0, start.x, start.y)));
+
+		while let Some(Reverse((_, cost, x, y))) = open.pop() {
+			let cell = UVec2::new(x, y);
+			if cell == goal {
+				let mut route = vec![goal];
+				let mut current = goal;
+				while let Some(previous) = came_from.get(&current) {
+					route.push(*previous);
+					current = *previous;
+				}
+				route.reverse();
+				return Some(route);
+			}
+			if cost > costs[&cell] {
+				// already found a cheaper way here
+				continue;
+			}
+			for neighbor in self.neighbors(cell) {
+				let neighbor_cost = cost + 1;
+				if costs.get(&neighbor).is_none_or(|existing| neighbor_cost < *existing) {
+					costs.insert(neighbor, neighbor_cost);
+					came_from.insert(neighbor, cell);
+					open.push(Reverse((neighbor_cost + heuristic(neighbor), neighbor_cost, neighbor.x, neighbor.y)));
+				}
+			}
+		}
+		None
+	}
+
+	/// Routes from every spawn to the exit, or `None` if any spawn can't reach it.
+	pub fn routes(&self) -> Option<Vec<(u64, Vec<UVec2>)>> {
+		self.spawns.iter()
+			.map(|spawn| Some((spawn.path_id, self.find_route(spawn.cell, self.exit)?)))
+			.collect()
+	}
+
+	/// Whether a tower can go on this cell without cutting any spawn off from the exit.
+	pub fn can_build(&mut self, cell: UVec2) -> bool {
+		if !self.is_walkable(cell) || cell == self.exit || self.spawns.iter().any(|spawn| spawn.cell == cell) {
+			return false;
+		}
+		self.set_cell(cell, GridCell::Tower);
+		let blocked = self.routes().is_none();
+		self.set_cell(cell, GridCell::Open);
+		!blocked
+	}
+
+	/// A path for each spawn that can reach the exit.
+	pub fn build_paths(&self) -> Vec<map::Path> {
+		self.spawns.iter()
+			.filter_map(|spawn| {
+				let route = self.find_route(spawn.cell, self.exit);
+				if route.is_none() {
+					warn!("Grid spawn for path {} can't reach the exit", spawn.path_id);
+				}
+				Some(map::Path::new(spawn.path_id, self.route_points(&route?)))
+			})
+			.collect()
+	}
+
+	/// A path from `position` to the exit, for an enemy that is somewhere its spawn's route doesn't go through anymore.
+	/// Every detour gets an id of its own.
+	pub fn build_detour(&mut self, position: Vec3) -> Option<map::Path> {
+		let route = self.find_route(self.cell_at(position)?, self.exit)?;
+		let mut points = vec![position];
+		// head straight for the next corner, instead of back to the middle of the cell first
+		points.extend(self.route_points(&route).into_iter().skip(1));
+		let id = self.next_detour_id;
+		self.next_detour_id += 1;
+		Some(map::Path::new(id, points))
+	}
+
+	/// World positions of the corners along the route. Cells in a straight line are skipped.
+	fn route_points(&self, route: &[UVec2]) -> Vec<Vec3> {
+		let mut points = vec![];
+		for (i, cell) in route.iter().enumerate() {
+			let is_corner = i == 0 || i == route.len() - 1
+				|| cell.as_ivec2() - route[i - 1].as_ivec2() != route[i + 1].as_ivec2() - cell.as_ivec2();
+			if is_corner {
+				points.push(self.cell_center(*cell));
+			}
+		}
+		if points.len() == 1 {
+			// a spawn on the exit still needs a path to walk along
+			points.push(points[0]);
+		}
+		points
+	}
+}
+
+/// A path that takes one enemy from where it was when its route got blocked to the exit.
+/// Removed once no enemy is on it anymore.
+#[derive(Component, Debug)]
+pub struct GridDetour;
+
+/// Meshes and materials for the grid.
+pub struct GridAssets {
+	wall_mesh: Handle<Mesh>,
+	wall_material: Handle<StandardMaterial>,
+}
+
+pub(crate) fn add_grid_assets(
+	mut commands: Commands,
+	mut meshes: ResMut<Assets<Mesh>>,
+	mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+	commands.insert_resource(GridAssets {
+		wall_mesh: meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
+		wall_material: materials.add(StandardMaterial {
+			base_color: Color::rgb(0.3, 0.3, 0.35),
+			..Default::default()
+		}),
+	});
+}
+
+/// Spawn the walls of the grid.
+pub(crate) fn spawn_grid(commands: &mut Commands, grid: &MazeGrid, assets: &GridAssets) {
+	for y in 0..grid.height {
+		for x in 0..grid.width {
+			let cell = UVec2::new(x, y);
+			if grid.cell(cell) != Some(GridCell::Wall) {
+				continue;
+			}
+			commands.spawn_bundle(
+				PbrBundle {
+					mesh: assets.wall_mesh.clone(),
+					material: assets.wall_material.clone(),
+					transform: Transform::from_translation(grid.cell_center(cell))
+						.with_scale(Vec3::splat(grid.cell_size)),
+					..Default::default()
+				}
+			)
+				.insert(LevelEntity);
+		}
+	}
+}
+
+/// Place towers on the grid with left click, as long as they leave a way to the exit.
+/// Enemies that are already on the grid are moved on to the new routes where they cross them, and the others get a
+/// [`GridDetour`] from where they are.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn place_grid_towers(
+	mut commands: Commands,
+	grid: Option<ResMut<MazeGrid>>,
+	editor: Res<EditorState>,
+	windows: Res<Windows>,
+	actions: Res<ActionState>,
+	tower_assets: Res<TowerAssets>,
+	cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+	mut players: Query<&mut Player>,
+	mut paths: Query<&mut map::Path, Without<GridDetour>>,
+	detours: Query<&map::Path, With<GridDetour>>,
+	mut enemies: Query<(&mut Enemy, &Transform)>,
+) {
+	let mut grid = match grid {
+		Some(grid) => grid,
+		None => return,
+	};
+	if editor.enabled || !actions.just_pressed(Action::PlaceTower) {
+		return;
+	}
+	let cell = cameras.iter().next()
+		.and_then(|(camera, camera_transform)| camera::cursor_ray(&windows, camera, camera_transform))
+		.and_then(|ray| ray.intersect_plane(grid.origin, Vec3::Z))
+		.and_then(|point| grid.cell_at(point));
+	let cell = match cell {
+		Some(cell) => cell,
+		None => return,
+	};
+
+	if enemies.iter().any(|(_, transform)| grid.cell_at(transform.translation) == Some(cell)) {
+		info!("Can't build on top of an enemy");
+		return;
+	}
+	if !grid.can_build(cell) {
+		info!("Can't build there, it would block the way to the exit");
+		return;
+	}
+	let mut player = match players.get_single_mut() {
+		Ok(player) => player,
+		Err(_) => return,
+	};
+	if player.make_purchase(towers::TOWER_COST).is_err() {
+		info!("Not enough money to build a tower");
+		return;
+	}
+
+	grid.set_cell(cell, GridCell::Tower);
+	// built towers go away with the level, since the reloaded grid won't have them
+	let tower = towers::spawn_tower(&mut commands, &tower_assets, grid.cell_center(cell), TowerAttackType::default());
+	commands.entity(tower).insert(LevelEntity);
+
+	let new_paths: HashMap<u64, map::Path> = grid.build_paths().into_iter()
+		.map(|path| (path.id, path))
+		.collect();
+	for mut path in paths.iter_mut() {
+		if let Some(new_path) = new_paths.get(&path.id) {
+			*path = new_path.clone();
+		}
+	}
+	let routes: HashMap<u64, Vec<UVec2>> = grid.routes().unwrap_or_default().into_iter().collect();
+	let detour_ids: Vec<u64> = detours.iter().map(|path| path.id).collect();
+	for (mut enemy, transform) in enemies.iter_mut() {
+		let on_grid = new_paths.contains_key(&enemy.path_id) || detour_ids.contains(&enemy.path_id);
+		if !on_grid || enemy.is_flying() {
+			continue;
+		}
+		let cell = grid.cell_at(transform.translation);
+		let on_new_route = cell.is_some_and(|cell| routes.get(&enemy.path_id).is_some_and(|route| route.contains(&cell)));
+		if on_new_route {
+			enemy.path_pos = new_paths[&enemy.path_id].closest_distance(transform.translation);
+			continue;
+		}
+		// jumping on to the new route could go through walls and towers, so walk to the exit from here instead
+		match grid.build_detour(transform.translation) {
+			Some(detour) => {
+				enemy.path_id = detour.id;
+				enemy.path_pos = 0.;
+				enemy.next_path_id = None;
+				commands.spawn()
+					.insert(detour)
+					.insert(GridDetour)
+					.insert(LevelEntity);
+			}
+			None => warn!("Enemy at {} has no way to the exit", transform.translation),
+		}
+	}
+}
+
+/// Despawn detours once every enemy on them has escaped or died.
+pub(crate) fn remove_unused_detours(
+	mut commands: Commands,
+	detours: Query<(Entity, &map::Path), With<GridDetour>>,
+	enemies: Query<&Enemy>,
+) {
+	for (entity, detour) in detours.iter() {
+		if !enemies.iter().any(|enemy| enemy.path_id == detour.id) {
+			commands.entity(entity).despawn();
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_find_route() {
+		let mut grid = MazeGrid::new(5, 5, UVec2::new(4, 0));
+		for y in 0..4 {
+			grid.set_cell(UVec2::new(2, y), GridCell::Wall);
+		}
+		let route = grid.find_route(UVec2::new(0, 0), UVec2::new(4, 0)).unwrap();
+		assert_eq!(route.first(), Some(&UVec2::new(0, 0)));
+		assert_eq!(route.last(), Some(&UVec2::new(4, 0)));
+		// around the wall: 4 across and 4 up and back down
+		assert_eq!(route.len(), 13);
+		assert!(route.iter().all(|cell| grid.is_walkable(*cell)));
+		for pair in route.windows(2) {
+			let step = (pair[0].as_ivec2() - pair[1].as_ivec2()).abs();
+			assert_eq!(step.x + step.y, 1);
+		}
+
+		grid.set_cell(UVec2::new(2, 4), GridCell::Wall);
+		assert_eq!(grid.find_route(UVec2::new(0, 0), UVec2::new(4, 0)), None);
+	}
+
+	#[test]
+	fn test_can_build() {
+		let mut grid = MazeGrid::new(3, 2, UVec2::new(2, 0))
+			.with_spawn(0, UVec2::new(0, 0));
+		assert!(grid.can_build(UVec2::new(1, 0)));
+		grid.set_cell(UVec2::new(1, 0), GridCell::Tower);
+		// would cut the spawn off from the exit
+		assert!(!grid.can_build(UVec2::new(1, 1)));
+		assert!(grid.is_walkable(UVec2::new(1, 1)));
+		assert!(!grid.can_build(UVec2::new(0, 0)));
+		assert!(!grid.can_build(UVec2::new(2, 0)));
+		assert!(!grid.can_build(UVec2::new(5, 5)));
+	}
+
+	#[test]
+	fn test_build_paths() {
+		let grid = MazeGrid::new(4, 3, UVec2::new(3, 2))
+			.with_origin(Vec3::new(-1., -1., 0.), 2.)
+			.with_spawn(7, UVec2::new(0, 2));
+		let paths = grid.build_paths();
+		assert_eq!(paths.len(), 1);
+		assert_eq!(paths[0].id, 7);
+		// a straight line only needs its ends
+		assert_eq!(paths[0].points().collect::<Vec<_>>(), vec![Vec3::new(-1., 3., 0.), Vec3::new(5., 3., 0.)]);
+	}
+
+	#[test]
+	fn test_build_detour() {
+		let mut grid = MazeGrid::new(3, 3, UVec2::new(2, 2))
+			.with_spawn(0, UVec2::new(0, 0));
+		grid.set_cell(UVec2::new(1, 0), GridCell::Wall);
+		grid.set_cell(UVec2::new(1, 1), GridCell::Wall);
+		let position = Vec3::new(0.2, 0.3, 0.);
+		let detour = grid.build_detour(position).unwrap();
+		assert!(detour.id >= FIRST_DETOUR_ID);
+		// up past the walls, then across to the exit
+		assert_eq!(detour.points().collect::<Vec<_>>(), vec![position, Vec3::new(0., 2., 0.), Vec3::new(2., 2., 0.)]);
+		assert_ne!(grid.build_detour(position).unwrap().id, detour.id);
+
+		grid.set_cell(UVec2::new(1, 2), GridCell::Wall);
+		assert!(grid.build_detour(position).is_none());
+	}
+
+	#[test]
+	fn test_cell_at() {
+		let grid = MazeGrid::new(4, 3, UVec2::ZERO).with_origin(Vec3::new(-1., -1., 0.), 2.);
+		assert_eq!(grid.cell_at(Vec3::new(-1.5, -0.5, 0.)), Some(UVec2::new(0, 0)));
+		assert_eq!(grid.cell_at(Vec3::new(3.2, 2.9, 0.)), Some(UVec2::new(2, 2)));
+		assert_eq!(grid.cell_at(Vec3::new(-3., 0., 0.)), None);
+		assert_eq!(grid.cell_at(Vec3::new(0., 9., 0.)), None);
+		assert_eq!(grid.cell_center(UVec2::new(2, 1)), Vec3::new(3., 1., 0.));
+	}
+}
@@ -0,0 +1,250 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::camera::CameraKeysBlocked;
+use crate::input::{Action, ActionState};
+use crate::pid_controller::{PidControlled, PidControlledRotation};
+
+use super::towers::Aim;
+
+/// How many frames of error are plotted.
+const HISTORY_LENGTH: usize = 120;
+/// How much one key press changes a gain by.
+const GAIN_STEP: f32 = 1.1;
+/// Gains that are zero are raised to this, because scaling them wouldn't do anything.
+const MIN_GAIN: f32 = 0.01;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Gain {
+	#[default]
+	Proportional,
+	Integral,
+	Derivative,
+}
+
+/// Lets the gains of the towers' aim controllers be tweaked while the game is running, and plots how far off target
+/// the selected tower is over time.
+///
+/// - F3 ([`Action::ToggleInspector`]): toggle the inspector
+/// - Tab ([`Action::InspectNextTower`]): select the next tower
+/// - 1 / 2 / 3 ([`Action::SelectProportionalGain`] and so on): choose the proportional, integral or derivative gain
+/// - Page Up / Page Down ([`Action::RaiseGain`], [`Action::LowerGain`]): raise or lower the chosen gain
+///
+/// The keyboard doesn't move the camera while the inspector is open.
+#[derive(Debug, Default)]
+pub struct PidInspector {
+	pub enabled: bool,
+	pub selected: Option<Entity>,
+	gain: Gain,
+	/// Angle to the target in radians, oldest first.
+	history: VecDeque<f32>,
+}
+
+impl PidInspector {
+	fn gain_mut<'a, C>(&self, controller: &'a mut PidControlled<Vec3, C>) -> &'a mut Vec3 {
+		match self.gain {
+			Gain::Proportional => &mut controller.proportional_gain,
+			Gain::Integral => &mut controller.integral_gain,
+			Gain::Derivative => &mut controller.derivative_gain,
+		}
+	}
+
+	fn record(&mut self, error: f32) {
+		if self.history.len() == HISTORY_LENGTH {
+			self.history.pop_front();
+		}
+		self.history.push_back(error);
+	}
+}
+
+/// Everything that is shown while the inspector is enabled.
+#[derive(Component)]
+pub(crate) struct InspectorUi;
+
+#[derive(Component)]
+pub(crate) struct InspectorText;
+
+/// One column of the error plot, the newest at the right.
+#[derive(Component)]
+pub(crate) struct PlotBar(usize);
+
+pub(crate) fn setup_inspector_ui(mut commands: Commands, asset_server: Res<AssetServer>) {
+	let hidden = Visibility { is_visible: false };
+	commands
+		.spawn_bundle(NodeBundle {
+			style: Style {
+				position_type: PositionType::Absolute,
+				position: Rect {
+					left: Val::Px(10.0),
+					bottom: Val::Px(10.0),
+					..Default::default()
+				},
+				flex_direction: FlexDirection::ColumnReverse,
+				..Default::default()
+			},
+			color: Color::rgba(0., 0., 0., 0.5).into(),
+			visibility: hidden.clone(),
+			..Default::default()
+		})
+		.insert(InspectorUi)
+		.with_children(|parent| {
+			parent
+				.spawn_bundle(TextBundle {
+					text: Text::with_section(
+						"",
+						TextStyle {
+							font: asset_server.load("fonts/Hack-Regular.ttf"),
+							font_size: 18.0,
+							color: Color::YELLOW,
+						},
+						Default::default(),
+					),
+					visibility: hidden.clone(),
+					..Default::default()
+				})
+				.insert(InspectorUi)
+				.insert(InspectorText);
+			parent
+				.spawn_bundle(NodeBundle {
+					style: Style {
+						size: Size::new(Val::Px(HISTORY_LENGTH as f32 * 3.0), Val::Px(100.0)),
+						align_items: AlignItems::FlexEnd,
+						..Default::default()
+					},
+					color: Color::NONE.into(),
+					visibility: hidden.clone(),
+					..Default::default()
+				})
+				.insert(InspectorUi)
+				.with_children(|plot| {
+					for i in 0..HISTORY_LENGTH {
+						plot
+							.spawn_bundle(NodeBundle {
+								style: Style {
+									size: Size::new(Val::Px(3.0), Val::Percent(0.0)),
+									..Default::default()
+								},
+								color: Color::ORANGE.into(),
+								visibility: hidden.clone(),
+								..Default::default()
+							})
+							.insert(InspectorUi)
+							.insert(PlotBar(i));
+					}
+				});
+		});
+}
+
+/// Toggle the inspector with [`Action::ToggleInspector`].
+pub(crate) fn toggle_inspector(
+	actions: Res<ActionState>,
+	mut inspector: ResMut<PidInspector>,
+	mut camera_keys: ResMut<CameraKeysBlocked>,
+	mut ui: Query<&mut Visibility, With<InspectorUi>>,
+) {
+	if !actions.just_pressed(Action::ToggleInspector) {
+		return;
+	}
+	inspector.enabled = !inspector.enabled;
+	camera_keys.0 = inspector.enabled;
+	info!("PID inspector: {}", inspector.enabled);
+	for mut visibility in ui.iter_mut() {
+		visibility.is_visible = inspector.enabled;
+	}
+}
+
+/// Select towers, change their gains, and record how far off target the selected one is.
+pub(crate) fn inspect_controllers(
+	actions: Res<ActionState>,
+	mut inspector: ResMut<PidInspector>,
+	mut controllers: Query<(Entity, &mut PidControlledRotation<Aim>, &Transform)>,
+) {
+	if !inspector.enabled {
+		return;
+	}
+
+	if actions.just_pressed(Action::InspectNextTower) || inspector.selected.is_some_and(|entity| controllers.get(entity).is_err()) {
+		let mut entities: Vec<Entity> = controllers.iter().map(|(entity, ..)| entity).collect();
+		entities.sort();
+		let next = match inspector.selected {
+			Some(selected) => entities.iter().position(|entity| *entity > selected).unwrap_or(0),
+			None => 0,
+		};
+		inspector.selected = entities.get(next).copied();
+		inspector.history.clear();
+	}
+
+	for (action, gain) in [
+		(Action::SelectProportionalGain, Gain::Proportional),
+		(Action::SelectIntegralGain, Gain::Integral),
+		(Action::SelectDerivativeGain, Gain::Derivative),
+	] {
+		if actions.just_pressed(action) {
+			inspector.gain = gain;
+		}
+	}
+
+	let (mut controller, transform) = match inspector.selected.and_then(|entity| controllers.get_mut(entity).ok()) {
+		Some((_, controller, transform)) => (controller, transform),
+		None => return,
+	};
+	let gain = inspector.gain_mut(&mut controller.controller);
+	if actions.just_pressed(Action::RaiseGain) {
+		*gain = (*gain * GAIN_STEP).max(Vec3::splat(MIN_GAIN));
+	}
+	if actions.just_pressed(Action::LowerGain) {
+		*gain /= GAIN_STEP;
+	}
+	let error = controller.angle_to_target(transform.rotation);
+	inspector.record(error);
+}
+
+/// One number if every axis has the same gain, otherwise all three.
+fn format_gain(gain: Vec3) -> String {
+	if gain == Vec3::splat(gain.x) {
+		format!("{:.3}", gain.x)
+	} else {
+		format!("[{:.3}, {:.3}, {:.3}]", gain.x, gain.y, gain.z)
+	}
+}
+
+pub(crate) fn update_inspector_ui(
+	inspector: Res<PidInspector>,
+	controllers: Query<&PidControlledRotation<Aim>>,
+	mut texts: Query<&mut Text, With<InspectorText>>,
+	mut bars: Query<(&PlotBar, &mut Style)>,
+) {
+	if !inspector.enabled {
+		return;
+	}
+	let controller = inspector.selected.and_then(|entity| controllers.get(entity).ok());
+	// the plot is scaled to fit the largest error, but never less than a degree
+	let largest = inspector.history.iter().copied().fold(1f32.to_radians(), f32::max);
+
+	for mut text in texts.iter_mut() {
+		text.sections[0].value = match (inspector.selected, controller) {
+			(Some(entity), Some(controller)) => {
+				let gains = &controller.controller;
+				let marker = |gain| if inspector.gain == gain { ">" } else { " " };
+				format!(
+					"PID INSPECTOR - tower {}\n{} P: {}\n{} I: {}\n{} D: {}\nerror: {:.1} deg (plot max {:.1} deg)",
+					entity.id(),
+					marker(Gain::Proportional), format_gain(gains.proportional_gain),
+					marker(Gain::Integral), format_gain(gains.integral_gain),
+					marker(Gain::Derivative), format_gain(gains.derivative_gain),
+					inspector.history.back().copied().unwrap_or(0.).to_degrees(),
+					largest.to_degrees(),
+				)
+			}
+			_ => "PID INSPECTOR\nTab: select a tower".to_string(),
+		};
+	}
+
+	// right-align the history, so that the newest error is always in the last column
+	let offset = HISTORY_LENGTH - inspector.history.len();
+	for (bar, mut style) in bars.iter_mut() {
+		let error = bar.0.checked_sub(offset).and_then(|i| inspector.history.get(i)).copied().unwrap_or(0.);
+		style.size.height = Val::Percent(error / largest * 100.);
+	}
+}
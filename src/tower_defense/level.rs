@@ -0,0 +1,688 @@
+use bevy::{
+	prelude::*,
+	asset::{AssetLoader, AssetServerSettings, FileAssetIo, HandleId, LoadContext, LoadedAsset},
+	reflect::TypeUuid,
+	utils::{BoxedFuture, HashSet},
+};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::camera::{CameraCommand, CameraView};
+
+use super::{
+	camera_focus,
+	enemy::{Enemy, EnemyCreateOptions},
+	grid::{self, GridAssets, GridCell, GridDetour, GridSpawn, MazeGrid},
+	map::{self, PathBranch, PathInterpolation},
+	player::Player,
+	towers::{self, TowerAssets, TowerAttackType},
+	waves::{Wave, WaveManager, WaveStage},
+};
+
+/// Everything that makes up a level. Loaded from `assets/levels/<name>.level.ron`.
+#[derive(Debug, Clone, Serialize, Deserialize, TypeUuid)]
+#[uuid = "ed5e8831-8281-4dfc-bf2a-a5d048605478"]
+pub struct Level {
+	pub paths: Vec<LevelPath>,
+	/// Ids of the paths that enemies enter the map on.
+	pub spawns: Vec<u64>,
+	/// Ids of the paths that lead enemies off the map.
+	pub exits: Vec<u64>,
+	/// Areas where the player is allowed to build towers.
+	#[serde(default)]
+	pub build_zones: Vec<BuildZone>,
+	/// Towers that are already placed when the level starts.
+	#[serde(default)]
+	pub towers: Vec<LevelTower>,
+	#[serde(default)]
+	pub scenery: Vec<Scenery>,
+	/// Makes this a maze level, where enemies find their own way through a grid instead of following `paths`.
+	#[serde(default)]
+	pub grid: Option<LevelGrid>,
+	/// Where the camera starts, and goes back to when it is reset.
+	#[serde(default)]
+	pub camera: Option<CameraView>,
+	pub starting_money: u64,
+	pub lives: u32,
+	pub waves: Vec<LevelWave>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LevelPath {
+	pub id: u64,
+	pub points: Vec<Vec3>,
+	#[serde(default)]
+	pub interpolation: PathInterpolation,
+	#[serde(default)]
+	pub branches: Vec<PathBranch>,
+}
+
+impl LevelPath {
+	pub fn from_path(path: &map::Path) -> Self {
+		Self {
+			id: path.id,
+			points: path.points().collect(),
+			interpolation: path.interpolation(),
+			branches: path.branches().to_vec(),
+		}
+	}
+
+	pub fn to_path(&self) -> map::Path {
+		let mut path = map::Path::new(self.id, self.points.clone())
+			.with_interpolation(self.interpolation);
+		for branch in self.branches.iter() {
+			path = path.with_branch(branch.path_id, branch.weight);
+		}
+		path
+	}
+}
+
+/// Layout of a maze level. See [`MazeGrid`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LevelGrid {
+	/// World position of the center of cell (0, 0).
+	pub origin: Vec3,
+	pub cell_size: f32,
+	pub width: u32,
+	pub height: u32,
+	#[serde(default)]
+	pub walls: Vec<UVec2>,
+	/// Each spawn gets its own path to the exit, with the given id.
+	pub spawns: Vec<GridSpawn>,
+	pub exit: UVec2,
+}
+
+impl LevelGrid {
+	/// The grid with its walls, and cells taken up by `towers`.
+	pub fn to_grid(&self, towers: &[LevelTower]) -> MazeGrid {
+		let mut grid = MazeGrid::new(self.width, self.height, self.exit)
+			.with_origin(self.origin, self.cell_size);
+		for spawn in self.spawns.iter() {
+			grid = grid.with_spawn(spawn.path_id, spawn.cell);
+		}
+		for wall in self.walls.iter() {
+			grid.set_cell(*wall, GridCell::Wall);
+		}
+		for tower in towers.iter() {
+			if let Some(cell) = grid.cell_at(tower.position) {
+				grid.set_cell(cell, GridCell::Tower);
+			}
+		}
+		grid
+	}
+}
+
+/// An axis aligned box that towers can be built in.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BuildZone {
+	pub min: Vec3,
+	pub max: Vec3,
+}
+
+impl BuildZone {
+	pub fn contains(&self, point: Vec3) -> bool {
+		point.cmpge(self.min).all() && point.cmple(self.max).all()
+	}
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LevelTower {
+	pub position: Vec3,
+	#[serde(default)]
+	pub attack_type: TowerAttackType,
+}
+
+/// Decorative objects that don't affect gameplay.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Scenery {
+	pub shape: SceneryShape,
+	pub position: Vec3,
+	#[serde(default = "default_scale")]
+	pub scale: Vec3,
+	pub color: Color,
+}
+
+fn default_scale() -> Vec3 {
+	Vec3::ONE
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum SceneryShape {
+	Cube { size: f32 },
+	Sphere { radius: f32 },
+	Plane { size: f32 },
+}
+
+impl From<SceneryShape> for Mesh {
+	fn from(shape: SceneryShape) -> Self {
+		match shape {
+			SceneryShape::Cube { size } => Mesh::from(shape::Cube { size }),
+			SceneryShape::Sphere { radius } => Mesh::from(shape::UVSphere { radius, sectors: 20, stacks: 20 }),
+			SceneryShape::Plane { size } => Mesh::from(shape::Plane { size }),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LevelWave {
+	pub enemy_count: u32,
+	/// Seconds between each enemy spawning.
+	pub spawn_rate: f32,
+	pub enemy: EnemyCreateOptions,
+}
+
+impl LevelWave {
+	pub fn to_wave(&self) -> Wave {
+		Wave {
+			stage: WaveStage::new(self.enemy_count, self.spawn_rate, self.enemy),
+		}
+	}
+}
+
+impl Level {
+	/// The level as it is written to its file.
+	pub fn to_ron(&self) -> anyhow::Result<String> {
+		let config = ron::ser::PrettyConfig::new().indentor("\t".to_string());
+		Ok(ron::ser::to_string_pretty(self, config)?)
+	}
+
+	/// Check the level for mistakes, like references to paths that don't exist.
+	/// Returns a description of each problem found.
+	pub fn validate(&self) -> Vec<String> {
+		let mut problems = vec![];
+		let mut path_ids = HashSet::default();
+		for path in self.paths.iter() {
+			if !path_ids.insert(path.id) {
+				problems.push(format!("Multiple paths have id {}", path.id));
+			}
+			if path.id >= grid::FIRST_DETOUR_ID {
+				problems.push(format!("Path id {} is too big, ids from {} on are for detours", path.id, grid::FIRST_DETOUR_ID));
+			}
+			if path.points.len() < 2 {
+				problems.push(format!("Path {} needs at least 2 points", path.id));
+			}
+		}
+		if let Some(level_grid) = &self.grid {
+			let grid = level_grid.to_grid(&self.towers);
+			for spawn in grid.spawns.iter() {
+				if !path_ids.insert(spawn.path_id) {
+					problems.push(format!("Multiple paths have id {}", spawn.path_id));
+				}
+				if spawn.path_id >= grid::FIRST_DETOUR_ID {
+					problems.push(format!("Path id {} is too big, ids from {} on are for detours", spawn.path_id, grid::FIRST_DETOUR_ID));
+				}
+				if !self.exits.contains(&spawn.path_id) {
+					problems.push(format!("Grid path {} always reaches the exit, so it should be an exit", spawn.path_id));
+				}
+				if grid.find_route(spawn.cell, grid.exit).is_none() {
+					problems.push(format!("Grid spawn at {} can't reach the exit", spawn.cell));
+				}
+			}
+		}
+		for path in self.paths.iter() {
+			for branch in path.branches.iter() {
+				if !path_ids.contains(&branch.path_id) {
+					problems.push(format!("Path {} branches to path {}, which does not exist", path.id, branch.path_id));
+				}
+			}
+			if path.branches.is_empty() && !self.exits.contains(&path.id) {
+				problems.push(format!("Path {} has no branches, but is not an exit", path.id));
+			}
+		}
+		for id in self.spawns.iter() {
+			if !path_ids.contains(id) {
+				problems.push(format!("Spawn path {} does not exist", id));
+			}
+		}
+		for id in self.exits.iter() {
+			if !path_ids.contains(id) {
+				problems.push(format!("Exit path {} does not exist", id));
+			}
+		}
+		for tower in self.towers.iter() {
+			if !self.build_zones.is_empty() && !self.build_zones.iter().any(|zone| zone.contains(tower.position)) {
+				problems.push(format!("Tower at {} is not in a build zone", tower.position));
+			}
+		}
+		for (i, wave) in self.waves.iter().enumerate() {
+			if !self.spawns.contains(&wave.enemy.path_id) {
+				problems.push(format!("Wave {} spawns enemies on path {}, which is not a spawn", i + 1, wave.enemy.path_id));
+			}
+		}
+		problems
+	}
+}
+
+#[derive(Default)]
+pub struct LevelLoader;
+
+impl AssetLoader for LevelLoader {
+	fn load<'a>(
+		&'a self,
+		bytes: &'a [u8],
+		load_context: &'a mut LoadContext,
+	) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+		Box::pin(async move {
+			let level: Level = ron::de::from_bytes(bytes)?;
+			load_context.set_default_asset(LoadedAsset::new(level));
+			Ok(())
+		})
+	}
+
+	fn extensions(&self) -> &[&str] {
+		&["level.ron"]
+	}
+}
+
+/// The level that is being played.
+pub struct CurrentLevel {
+	pub name: String,
+	pub handle: Handle<Level>,
+	/// What the editor last wrote to the level's file. The world already matches it, so the game isn't restarted
+	/// when the file is reloaded.
+	pub editor_save: Option<String>,
+}
+
+impl CurrentLevel {
+	pub fn new(name: &str) -> Self {
+		Self {
+			name: name.to_string(),
+			handle: Default::default(),
+			editor_save: None,
+		}
+	}
+
+	/// Path of the level's file, relative to the assets folder.
+	pub fn asset_path(&self) -> String {
+		format!("levels/{}.level.ron", self.name)
+	}
+}
+
+/// Where a file in the assets folder is on disk, for reading or writing it directly instead of through the asset
+/// server.
+pub fn asset_file_path(asset_settings: Option<&AssetServerSettings>, path: &str) -> PathBuf {
+	let asset_folder = asset_settings
+		.map(|settings| settings.asset_folder.as_str())
+		.unwrap_or("assets");
+	FileAssetIo::get_root_path()
+		.join(asset_folder)
+		.join(path)
+}
+
+/// Marks entities that were spawned from the level or built while playing it, so they can be removed when the level
+/// is reloaded.
+#[derive(Component, Debug)]
+pub struct LevelEntity;
+
+/// Part of the visuals for the path with this id. Rebuilt whenever the path changes.
+#[derive(Component, Debug)]
+pub struct PathVisual {
+	pub path_id: u64,
+}
+
+/// The marker that shows where a path's node is.
+#[derive(Component, Debug)]
+pub struct PathNodeMarker {
+	pub path_id: u64,
+	pub index: usize,
+}
+
+/// Assets used to display the level.
+pub struct LevelAssets {
+	node_mesh: Handle<Mesh>,
+	visualizer_mesh: Handle<Mesh>,
+	path_material: Handle<StandardMaterial>,
+	marker_mesh: Handle<Mesh>,
+	spawn_material: Handle<StandardMaterial>,
+	exit_material: Handle<StandardMaterial>,
+	build_zone_mesh: Handle<Mesh>,
+	build_zone_material: Handle<StandardMaterial>,
+}
+
+pub(crate) fn add_level_assets(
+	mut commands: Commands,
+	mut meshes: ResMut<Assets<Mesh>>,
+	mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+	commands.insert_resource(LevelAssets {
+		node_mesh: meshes.add(Mesh::from(shape::UVSphere {
+			radius: 0.2,
+			sectors: 20,
+			stacks: 20,
+		})),
+		visualizer_mesh: meshes.add(Mesh::from(shape::UVSphere {
+			radius: 0.05,
+			sectors: 10,
+			stacks: 10,
+		})),
+		path_material: materials.add(StandardMaterial {
+			base_color: Color::WHITE,
+			metallic: 1.,
+			perceptual_roughness: 0.5,
+			..Default::default()
+		}),
+		marker_mesh: meshes.add(Mesh::from(shape::UVSphere {
+			radius: 0.5,
+			sectors: 20,
+			stacks: 20,
+		})),
+		spawn_material: materials.add(StandardMaterial {
+			base_color: Color::rgba(0.2, 1.0, 0.2, 0.5),
+			alpha_mode: AlphaMode::Blend,
+			unlit: true,
+			..Default::default()
+		}),
+		exit_material: materials.add(StandardMaterial {
+			base_color: Color::rgba(1.0, 0.2, 0.2, 0.5),
+			alpha_mode: AlphaMode::Blend,
+			unlit: true,
+			..Default::default()
+		}),
+		build_zone_mesh: meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
+		build_zone_material: materials.add(StandardMaterial {
+			base_color: Color::rgba(0.2, 0.4, 1.0, 0.15),
+			alpha_mode: AlphaMode::Blend,
+			unlit: true,
+			..Default::default()
+		}),
+	});
+}
+
+pub(crate) fn load_current_level(
+	asset_server: Res<AssetServer>,
+	mut current_level: ResMut<CurrentLevel>,
+) {
+	info!("Loading level {}", current_level.name);
+	current_level.handle = asset_server.load(current_level.asset_path().as_str());
+}
+
+/// Sets up the world from the current level whenever it finishes loading, or its file is changed.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub(crate) fn apply_level(
+	mut commands: Commands,
+	mut level_events: EventReader<AssetEvent<Level>>,
+	current_level: Res<CurrentLevel>,
+	levels: Res<Assets<Level>>,
+	level_assets: Res<LevelAssets>,
+	tower_assets: Res<TowerAssets>,
+	grid_assets: Res<GridAssets>,
+	mut meshes: ResMut<Assets<Mesh>>,
+	mut materials: ResMut<Assets<StandardMaterial>>,
+	mut wave_manager: ResMut<WaveManager>,
+	mut players: Query<&mut Player>,
+	mut camera_commands: EventWriter<CameraCommand>,
+	old_entities: Query<Entity, Or<(With<LevelEntity>, With<Enemy>)>>,
+	// the level that was set up last, so that the camera stays where it is when that level is reloaded
+	mut applied: Local<Option<HandleId>>,
+) {
+	let mut changed = false;
+	for event in level_events.iter() {
+		match event {
+			AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+				changed |= *handle == current_level.handle;
+			}
+			AssetEvent::Removed { .. } => {}
+		}
+	}
+	if !changed {
+		return;
+	}
+	let level = match levels.get(&current_level.handle) {
+		Some(level) => level,
+		None => return,
+	};
+	if current_level.editor_save.is_some() && current_level.editor_save == level.to_ron().ok() {
+		debug!("Level {} was saved by the editor, not setting it up again", current_level.name);
+		return;
+	}
+	// keep playing the current level instead of building a broken one, so that a bad save doesn't crash the game
+	let problems = level.validate();
+	if !problems.is_empty() {
+		for problem in problems {
+			error!("Level {}: {}", current_level.name, problem);
+		}
+		warn!("Not setting up level {}, fix the problems above", current_level.name);
+		return;
+	}
+	info!("Setting up level {}", current_level.name);
+
+	for entity in old_entities.iter() {
+		commands.entity(entity).despawn_recursive();
+	}
+
+	let mut paths: Vec<map::Path> = level.paths.iter().map(LevelPath::to_path).collect();
+	match &level.grid {
+		Some(level_grid) => {
+			let grid = level_grid.to_grid(&level.towers);
+			paths.extend(grid.build_paths());
+			grid::spawn_grid(&mut commands, &grid, &grid_assets);
+			commands.insert_resource(grid);
+		}
+		None => commands.remove_resource::<MazeGrid>(),
+	}
+	map::update_exit_distances(&mut paths);
+	for path in paths {
+		commands.spawn()
+			.insert(path)
+			.insert(LevelEntity);
+	}
+	for zone in level.build_zones.iter() {
+		spawn_build_zone(&mut commands, *zone, &level_assets);
+	}
+	for tower in level.towers.iter() {
+		let entity = towers::spawn_tower(&mut commands, &tower_assets, tower.position, tower.attack_type);
+		commands.entity(entity).insert(LevelEntity);
+	}
+	for scenery in level.scenery.iter() {
+		commands.spawn_bundle(
+			PbrBundle {
+				mesh: meshes.add(Mesh::from(scenery.shape)),
+				material: materials.add(StandardMaterial {
+					base_color: scenery.color,
+					..Default::default()
+				}),
+				transform: Transform::from_translation(scenery.position).with_scale(scenery.scale),
+				..Default::default()
+			}
+		)
+			.insert(LevelEntity);
+	}
+
+	*wave_manager = WaveManager::new(level.waves.iter().map(LevelWave::to_wave).collect());
+	for mut player in players.iter_mut() {
+		*player = Player::new()
+			.with_money(level.starting_money)
+			.with_lives(level.lives);
+	}
+	camera_commands.send(CameraCommand::SetHome(level.camera.unwrap_or(camera_focus::DEFAULT_VIEW)));
+	if *applied != Some(current_level.handle.id) {
+		camera_commands.send(CameraCommand::Reset);
+		*applied = Some(current_level.handle.id);
+	}
+}
+
+/// Spawns an entity for the build zone, scaled to fill it.
+pub fn spawn_build_zone(commands: &mut Commands, zone: BuildZone, assets: &LevelAssets) -> Entity {
+	commands.spawn_bundle(
+		PbrBundle {
+			mesh: assets.build_zone_mesh.clone(),
+			material: assets.build_zone_material.clone(),
+			transform: build_zone_transform(&zone),
+			..Default::default()
+		}
+	)
+		.insert(zone)
+		.insert(LevelEntity)
+		.id()
+}
+
+pub fn build_zone_transform(zone: &BuildZone) -> Transform {
+	let size = zone.max - zone.min;
+	Transform::from_translation(zone.min + size / 2.).with_scale(size)
+}
+
+/// Rebuilds the visuals for paths whenever they change.
+pub(crate) fn sync_path_visuals(
+	mut commands: Commands,
+	current_level: Res<CurrentLevel>,
+	levels: Res<Assets<Level>>,
+	assets: Res<LevelAssets>,
+	paths: Query<&map::Path, (Changed<map::Path>, Without<GridDetour>)>,
+	visuals: Query<(Entity, &PathVisual)>,
+) {
+	let level = levels.get(&current_level.handle);
+	for path in paths.iter() {
+		for (entity, visual) in visuals.iter() {
+			if visual.path_id == path.id {
+				commands.entity(entity).despawn();
+			}
+		}
+
+		for (index, point) in path.points().enumerate() {
+			commands.spawn_bundle(
+				PbrBundle {
+					mesh: assets.node_mesh.clone(),
+					material: assets.path_material.clone(),
+					transform: Transform::from_translation(point),
+					..Default::default()
+				}
+			)
+				.insert(PathNodeMarker { path_id: path.id, index })
+				.insert(PathVisual { path_id: path.id })
+				.insert(LevelEntity);
+		}
+		let visualizers = 5;
+		for i in 0..path.node_count().saturating_sub(1) {
+			for j in 0..visualizers {
+				commands.spawn_bundle(
+					PbrBundle {
+						mesh: assets.visualizer_mesh.clone(),
+						material: assets.path_material.clone(),
+						..Default::default()
+					}
+				)
+					.insert(map::PathVisualizer {
+						path_id: path.id,
+						node_start: i,
+						node_end: i + 1,
+						offset: j as f32 / visualizers as f32,
+					})
+					.insert(PathVisual { path_id: path.id })
+					.insert(LevelEntity);
+			}
+		}
+
+		let marker = match level {
+			Some(level) if level.spawns.contains(&path.id) => {
+				Some((path.get_point_along_path(0.), assets.spawn_material.clone()))
+			}
+			Some(level) if level.exits.contains(&path.id) => {
+				Some((path.get_point_along_path(path.total_length()), assets.exit_material.clone()))
+			}
+			_ => None,
+		};
+		if let Some((position, material)) = marker {
+			commands.spawn_bundle(
+				PbrBundle {
+					mesh: assets.marker_mesh.clone(),
+					material,
+					transform: Transform::from_translation(position),
+					..Default::default()
+				}
+			)
+				.insert(PathVisual { path_id: path.id })
+				.insert(LevelEntity);
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_default_level_is_valid() {
+		let level: Level = ron::de::from_str(include_str!("../../assets/levels/default.level.ron")).unwrap();
+		assert_eq!(level.validate(), Vec::<String>::new());
+		assert!(!level.paths.is_empty());
+		assert!(!level.waves.is_empty());
+	}
+
+	#[test]
+	fn test_maze_level_is_valid() {
+		let level: Level = ron::de::from_str(include_str!("../../assets/levels/maze.level.ron")).unwrap();
+		assert_eq!(level.validate(), Vec::<String>::new());
+		let grid = level.grid.as_ref().unwrap().to_grid(&level.towers);
+		assert_eq!(grid.cell(UVec2::new(4, 2)), Some(GridCell::Wall));
+		assert_eq!(grid.cell(UVec2::new(4, 5)), Some(GridCell::Tower));
+		assert_eq!(grid.build_paths().len(), 2);
+	}
+
+	#[test]
+	fn test_level_round_trip() {
+		let level: Level = ron::de::from_str(include_str!("../../assets/levels/default.level.ron")).unwrap();
+		let serialized = level.to_ron().unwrap();
+		let deserialized: Level = ron::de::from_str(&serialized).unwrap();
+		// the editor recognises its own saves by this
+		assert_eq!(deserialized.to_ron().unwrap(), serialized);
+		assert_eq!(deserialized.paths.len(), level.paths.len());
+		assert_eq!(deserialized.paths[3].points, level.paths[3].points);
+		assert_eq!(deserialized.paths[3].interpolation, level.paths[3].interpolation);
+		assert_eq!(deserialized.build_zones, level.build_zones);
+		assert_eq!(deserialized.waves.len(), level.waves.len());
+	}
+
+	#[test]
+	fn test_level_path_from_path() {
+		let level_path = LevelPath {
+			id: 3,
+			points: vec![Vec3::ZERO, Vec3::X, Vec3::Y],
+			interpolation: PathInterpolation::CatmullRom,
+			branches: vec![PathBranch { path_id: 4, weight: 2. }],
+		};
+		let converted = LevelPath::from_path(&level_path.to_path());
+		assert_eq!(converted.id, level_path.id);
+		assert_eq!(converted.points, level_path.points);
+		assert_eq!(converted.interpolation, level_path.interpolation);
+		assert_eq!(converted.branches, level_path.branches);
+	}
+
+	#[test]
+	fn test_validate_finds_missing_paths() {
+		let level = Level {
+			paths: vec![
+				LevelPath {
+					id: 0,
+					points: vec![Vec3::ZERO, Vec3::X],
+					interpolation: PathInterpolation::Linear,
+					branches: vec![PathBranch { path_id: 1, weight: 1. }],
+				},
+			],
+			spawns: vec![0],
+			exits: vec![2],
+			build_zones: vec![],
+			towers: vec![],
+			scenery: vec![],
+			grid: None,
+			camera: None,
+			starting_money: 0,
+			lives: 1,
+			waves: vec![],
+		};
+		assert_eq!(level.validate(), vec![
+			"Path 0 branches to path 1, which does not exist".to_string(),
+			"Exit path 2 does not exist".to_string(),
+		]);
+	}
+
+	#[test]
+	fn test_build_zone_contains() {
+		let zone = BuildZone {
+			min: Vec3::new(-1., -1., -1.),
+			max: Vec3::new(1., 2., 1.),
+		};
+		assert!(zone.contains(Vec3::ZERO));
+		assert!(zone.contains(Vec3::new(1., 2., 1.)));
+		assert!(!zone.contains(Vec3::new(0., 3., 0.)));
+	}
+}
@@ -1,11 +1,45 @@
-use bevy::prelude::*;
+use std::borrow::BorrowMut;
 
-#[derive(Component)]
+use bevy::{prelude::*, ecs::system::SystemParam, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+#[derive(Component, Debug, Clone)]
 pub struct Path {
 	pub id: u64,
 	nodes: Vec<PathNode>,
+	interpolation: PathInterpolation,
+	/// Points along the path that are close enough together to be linearly interpolated between.
+	/// For linear paths, these are the same as the nodes.
+	samples: Vec<PathNode>,
+	/// Paths that enemies can continue on to after reaching the end of this one.
+	/// A path with no branches ends at an exit.
+	branches: Vec<PathBranch>,
+	/// Shortest distance from the end of this path to an exit. See [`update_exit_distances`].
+	exit_distance: f32,
+}
+
+/// A path that enemies may take after finishing another path.
+/// Branches should start where the path they branch from ends.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PathBranch {
+	pub path_id: u64,
+	/// How likely enemies are to take this branch, relative to the other branches.
+	pub weight: f32,
 }
 
+/// How the path moves between its nodes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PathInterpolation {
+	/// Straight lines between nodes.
+	#[default]
+	Linear,
+	/// A smooth Catmull-Rom spline that passes through every node.
+	CatmullRom,
+}
+
+/// How many samples each segment of a curved path is split into.
+const CURVE_SAMPLES_PER_SEGMENT: usize = 16;
+
 #[derive(Debug, Clone, Copy)]
 struct PathNode {
 	pub(crate) point: Vec3,
@@ -19,68 +53,306 @@ impl Path {
 	pub fn new(id: u64, points: Vec<Vec3>) -> Self {
 		let mut path = Path {
 			id,
-			nodes: points.into_iter().map(|point| PathNode { point, percent: 0.0, distance: 0.0 }).collect()
+			nodes: points.into_iter().map(|point| PathNode { point, percent: 0.0, distance: 0.0 }).collect(),
+			interpolation: PathInterpolation::Linear,
+			samples: vec![],
+			branches: vec![],
+			exit_distance: 0.0,
 		};
-		path.update_distances();
-		path.update_path_percents();
+		path.update_samples();
 		path
 	}
 
-	pub fn points(&self) -> Vec<Vec3> {
-		self.nodes.iter().map(|node| node.point).collect()
+	/// Set how the path moves between its nodes.
+	pub fn with_interpolation(mut self, interpolation: PathInterpolation) -> Self {
+		self.interpolation = interpolation;
+		self.update_samples();
+		self
 	}
 
-	/// Total length of the path
-	pub fn total_length(&self) -> f32 {
-		self.nodes.last().map(|node| node.distance).unwrap_or(0.0)
+	/// Add a branch that enemies can take after reaching the end of this path.
+	pub fn with_branch(mut self, path_id: u64, weight: f32) -> Self {
+		self.branches.push(PathBranch { path_id, weight });
+		self
 	}
 
-	fn update_distances(&mut self) {
-		for i in 1..self.nodes.len() {
-			self.nodes[i].distance = self.nodes[i - 1].distance + self.nodes[i - 1].point.distance(self.nodes[i].point);
+	/// Pick a branch to continue on to, according to the branch weights.
+	/// `roll` should be a random number in `[0, 1)`.
+	pub fn choose_branch(&self, roll: f32) -> Option<u64> {
+		let total_weight: f32 = self.branches.iter().map(|branch| branch.weight).sum();
+		let mut remaining = roll * total_weight;
+		for branch in self.branches.iter() {
+			if remaining < branch.weight {
+				return Some(branch.path_id);
+			}
+			remaining -= branch.weight;
 		}
+		self.branches.last().map(|branch| branch.path_id)
+	}
+
+	/// Shortest distance to an exit from the given distance along this path.
+	pub fn distance_to_exit(&self, distance: f32) -> f32 {
+		(self.total_length() - distance).max(0.) + self.exit_distance
+	}
+
+	pub fn points(&self) -> impl ExactSizeIterator<Item = Vec3> + '_ {
+		self.nodes.iter().map(|node| node.point)
+	}
+
+	pub fn node_count(&self) -> usize {
+		self.nodes.len()
 	}
 
-	fn update_path_percents(&mut self) {
-		let total_length = self.total_length();
-		let mut dist_sum: f32 = 0.;
-		for i in 0..self.nodes.len() - 1 {
-			let distance = self.nodes[i + 1].distance - self.nodes[i].distance;
-			self.nodes[i].percent = dist_sum / total_length;
-			dist_sum += distance;
+	pub fn node_point(&self, index: usize) -> Vec3 {
+		self.nodes[index].point
+	}
+
+	/// Move the node at `index`.
+	pub fn set_point(&mut self, index: usize, point: Vec3) {
+		self.nodes[index].point = point;
+		self.update_samples();
+	}
+
+	/// Add a new node, so that it ends up at `index`.
+	pub fn insert_point(&mut self, index: usize, point: Vec3) {
+		self.nodes.insert(index, PathNode { point, percent: 0.0, distance: 0.0 });
+		self.update_samples();
+	}
+
+	/// Remove the node at `index`. Paths always keep at least 2 nodes, so this does nothing if there are only 2 left.
+	/// Returns whether the node was removed.
+	pub fn remove_point(&mut self, index: usize) -> bool {
+		if self.nodes.len() <= 2 {
+			return false;
+		}
+		self.nodes.remove(index);
+		self.update_samples();
+		true
+	}
+
+	pub fn interpolation(&self) -> PathInterpolation {
+		self.interpolation
+	}
+
+	pub fn branches(&self) -> &[PathBranch] {
+		&self.branches
+	}
+
+	/// Distance along the path to the node at `index`.
+	pub fn node_distance(&self, index: usize) -> f32 {
+		self.nodes[index].distance
+	}
+
+	/// Total length of the path
+	pub fn total_length(&self) -> f32 {
+		self.samples.last().map(|node| node.distance).unwrap_or(0.0)
+	}
+
+	/// Rebuild the samples that the path is interpolated through, and the distances along the path of every node.
+	fn update_samples(&mut self) {
+		let samples_per_segment = match self.interpolation {
+			PathInterpolation::Linear => 1,
+			PathInterpolation::CatmullRom => CURVE_SAMPLES_PER_SEGMENT,
+		};
+		self.samples = match self.interpolation {
+			PathInterpolation::Linear => self.nodes.clone(),
+			PathInterpolation::CatmullRom => {
+				let points: Vec<Vec3> = self.points().collect();
+				let mut samples = vec![];
+				for i in 0..points.len().saturating_sub(1) {
+					// the ends of the path are extended by mirroring their neighbors
+					let p0 = if i > 0 { points[i - 1] } else { points[i] * 2. - points[i + 1] };
+					let p3 = if i + 2 < points.len() { points[i + 2] } else { points[i + 1] * 2. - points[i] };
+					for j in 0..samples_per_segment {
+						let t = j as f32 / samples_per_segment as f32;
+						samples.push(catmull_rom(p0, points[i], points[i + 1], p3, t));
+					}
+				}
+				samples.extend(points.last());
+				samples.into_iter().map(|point| PathNode { point, percent: 0.0, distance: 0.0 }).collect()
+			}
+		};
+		update_distances(&mut self.samples);
+		update_path_percents(&mut self.samples);
+		for (i, node) in self.nodes.iter_mut().enumerate() {
+			let sample = &self.samples[i * samples_per_segment];
+			node.distance = sample.distance;
+			node.percent = sample.percent;
 		}
-		self.nodes.last_mut().map(|node| node.percent = 1.0);
 	}
 
 	/// Returns the point on the path at the given percent.
 	#[allow(dead_code)]
 	pub fn get_point_along_path_percent(&self, t: f32) -> Vec3 {
-		for i in 0..self.nodes.len() - 1 {
-			let p1 = &self.nodes[i];
-			let p2 = &self.nodes[i + 1];
-			if p1.percent <= t && t <= p2.percent {
-				let t = (t - p1.percent) / (p2.percent - p1.percent);
-				return p1.point.lerp(p2.point, t);
-			}
+		if self.samples.len() < 2 || t < 0. {
+			return self.samples.first().map(|node| node.point).unwrap_or(Vec3::new(0., 0., 0.));
 		}
-		if t < 0. {
-			return self.nodes.first().map(|node| node.point).unwrap_or(Vec3::new(0., 0., 0.));
-		} else {
-			return self.nodes.last().map(|node| node.point).unwrap_or(Vec3::new(0., 0., 0.));
+		if t > 1. {
+			return self.samples.last().map(|node| node.point).unwrap_or(Vec3::new(0., 0., 0.));
 		}
+		let i = self.segment_containing(|node| node.percent < t);
+		let p1 = &self.samples[i];
+		let p2 = &self.samples[i + 1];
+		let t = (t - p1.percent) / (p2.percent - p1.percent);
+		p1.point.lerp(p2.point, t)
 	}
 
 	/// Returns the point on the path at the given distance from the start.
 	pub fn get_point_along_path(&self, distance: f32) -> Vec3 {
-		for i in 0..self.nodes.len() - 1 {
-			let p1 = &self.nodes[i];
-			let p2 = &self.nodes[i + 1];
-			if p1.distance <= distance && distance <= p2.distance {
-				let t = (distance - p1.distance) / (p2.distance - p1.distance);
-				return p1.point.lerp(p2.point, t);
+		if self.samples.len() < 2 || distance < 0. || distance > self.total_length() {
+			return self.samples.last().map(|node| node.point).unwrap_or(Vec3::new(0., 0., 0.));
+		}
+		let i = self.segment_containing(|node| node.distance < distance);
+		let p1 = &self.samples[i];
+		let p2 = &self.samples[i + 1];
+		let t = (distance - p1.distance) / (p2.distance - p1.distance);
+		p1.point.lerp(p2.point, t)
+	}
+
+	/// Returns the direction of travel at the given distance from the start, as a unit vector.
+	pub fn get_direction_along_path(&self, distance: f32) -> Vec3 {
+		if self.samples.len() < 2 {
+			return Vec3::ZERO;
+		}
+		// distances off either end of the path use the closest segment
+		let i = self.segment_containing(|node| node.distance < distance);
+		(self.samples[i + 1].point - self.samples[i].point).normalize_or_zero()
+	}
+
+	/// Distance along the path to the point on it that is closest to `point`.
+	pub fn closest_distance(&self, point: Vec3) -> f32 {
+		let mut closest = (f32::INFINITY, 0.);
+		for segment in self.samples.windows(2) {
+			let start = segment[0].point;
+			let offset = segment[1].point - start;
+			let t = if offset.length_squared() > 0. {
+				((point - start).dot(offset) / offset.length_squared()).clamp(0., 1.)
+			} else {
+				0.
+			};
+			let distance_squared = point.distance_squared(start + offset * t);
+			if distance_squared < closest.0 {
+				closest = (distance_squared, segment[0].distance + (segment[1].distance - segment[0].distance) * t);
+			}
+		}
+		closest.1
+	}
+
+	/// Binary search for the index of the sample at the start of the segment where `is_before` stops being true.
+	/// Requires at least 2 samples.
+	fn segment_containing(&self, is_before: impl FnMut(&PathNode) -> bool) -> usize {
+		self.samples.partition_point(is_before).clamp(1, self.samples.len() - 1) - 1
+	}
+}
+
+/// Point on a uniform Catmull-Rom spline between `p1` and `p2`.
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+	let t2 = t * t;
+	let t3 = t2 * t;
+	0.5 * (
+		2. * p1
+		+ (p2 - p0) * t
+		+ (2. * p0 - 5. * p1 + 4. * p2 - p3) * t2
+		+ (3. * p1 - p0 - 3. * p2 + p3) * t3
+	)
+}
+
+fn update_distances(nodes: &mut [PathNode]) {
+	for i in 1..nodes.len() {
+		nodes[i].distance = nodes[i - 1].distance + nodes[i - 1].point.distance(nodes[i].point);
+	}
+}
+
+fn update_path_percents(nodes: &mut [PathNode]) {
+	let total_length = nodes.last().map(|node| node.distance).unwrap_or(0.0);
+	let mut dist_sum: f32 = 0.;
+	for i in 0..nodes.len().saturating_sub(1) {
+		let distance = nodes[i + 1].distance - nodes[i].distance;
+		nodes[i].percent = dist_sum / total_length;
+		dist_sum += distance;
+	}
+	if let Some(node) = nodes.last_mut() {
+		node.percent = 1.0;
+	}
+}
+
+/// Calculate how far away the end of each path is from an exit, by following the shortest branches.
+/// Needs to be called again whenever paths or their branches change.
+pub fn update_exit_distances<P: BorrowMut<Path>>(paths: &mut [P]) {
+	let mut exit_distances: HashMap<u64, f32> = paths.iter()
+		.map(|path| path.borrow())
+		.map(|path| (path.id, if path.branches.is_empty() { 0. } else { f32::INFINITY }))
+		.collect();
+	let lengths: HashMap<u64, f32> = paths.iter()
+		.map(|path| path.borrow())
+		.map(|path| (path.id, path.total_length()))
+		.collect();
+	// Relax until nothing changes. Every path is at most `paths.len()` branches away from an exit.
+	for _ in 0..paths.len() {
+		let mut changed = false;
+		for path in paths.iter().map(|path| path.borrow()) {
+			let shortest = path.branches.iter()
+				.filter_map(|branch| Some(lengths.get(&branch.path_id)? + exit_distances.get(&branch.path_id)?))
+				.fold(f32::INFINITY, f32::min);
+			let current = exit_distances.get_mut(&path.id).unwrap();
+			if shortest < *current {
+				*current = shortest;
+				changed = true;
+			}
+		}
+		if !changed {
+			break;
+		}
+	}
+	for path in paths.iter_mut().map(|path| path.borrow_mut()) {
+		path.exit_distance = exit_distances[&path.id];
+		if path.exit_distance.is_infinite() {
+			warn!("Path {} never reaches an exit", path.id);
+		}
+	}
+}
+
+/// Maps path ids to the entities that hold them, so paths can be found without searching through all of them.
+#[derive(Debug, Default)]
+pub struct PathLookup {
+	entities: HashMap<u64, Entity>,
+}
+
+impl PathLookup {
+	pub fn entity(&self, id: u64) -> Option<Entity> {
+		self.entities.get(&id).copied()
+	}
+}
+
+pub fn update_path_lookup(
+	mut lookup: ResMut<PathLookup>,
+	changed: Query<(Entity, &Path), Changed<Path>>,
+	removed: RemovedComponents<Path>,
+) {
+	for entity in removed.iter() {
+		lookup.entities.retain(|_, e| *e != entity);
+	}
+	for (entity, path) in changed.iter() {
+		// in case the path's id changed
+		lookup.entities.retain(|_, e| *e != entity);
+		if let Some(existing) = lookup.entities.insert(path.id, entity) {
+			if existing != entity {
+				warn!("Multiple paths have id {}", path.id);
 			}
 		}
-		self.nodes.last().map(|node| node.point).unwrap_or(Vec3::new(0., 0., 0.))
+	}
+}
+
+/// Finds paths by their id.
+#[derive(SystemParam)]
+pub struct Paths<'w, 's> {
+	lookup: Res<'w, PathLookup>,
+	paths: Query<'w, 's, &'static Path>,
+}
+
+impl<'w, 's> Paths<'w, 's> {
+	pub fn get(&self, id: u64) -> Option<&Path> {
+		self.paths.get(self.lookup.entity(id)?).ok()
 	}
 }
 
@@ -94,16 +366,18 @@ pub struct PathVisualizer {
 
 pub fn visualize_path(
 	time: Res<Time>,
-	paths: Query<&Path>,
+	paths: Paths,
 	mut visuals: Query<(&PathVisualizer, &mut Transform)>,
 ) {
 	for (marker, mut marker_transform) in visuals.iter_mut() {
-		let path = paths.iter()
-			.find(|path| path.id == marker.path_id)
-			.expect(format!("No path with id: {}", marker.path_id).as_str());
-		let pos_start = path.points()[marker.node_start];
-		let pos_end = path.points()[marker.node_end];
-		marker_transform.translation = pos_start.lerp(pos_end, ((time.time_since_startup().as_secs_f32() / 10.) + marker.offset) % 1.0);
+		let path = match paths.get(marker.path_id) {
+			Some(path) => path,
+			None => continue,
+		};
+		let distance_start = path.node_distance(marker.node_start);
+		let distance_end = path.node_distance(marker.node_end);
+		let t = ((time.time_since_startup().as_secs_f32() / 10.) + marker.offset) % 1.0;
+		marker_transform.translation = path.get_point_along_path(distance_start + (distance_end - distance_start) * t);
 	}
 }
 
@@ -162,3 +436,138 @@ fn test_get_point_along_path_distance() {
 	assert_eq!(path.get_point_along_path(0.), Vec3::new(0., 0., 0.));
 	assert_eq!(path.get_point_along_path(5.), Vec3::new(5., 0., 0.));
 }
+
+#[test]
+fn test_choose_branch() {
+	let path = Path::new(0, vec![Vec3::ZERO, Vec3::X])
+		.with_branch(1, 3.)
+		.with_branch(2, 1.);
+	assert_eq!(path.choose_branch(0.), Some(1));
+	assert_eq!(path.choose_branch(0.7), Some(1));
+	assert_eq!(path.choose_branch(0.8), Some(2));
+	assert_eq!(path.choose_branch(0.9999), Some(2));
+	assert_eq!(Path::new(0, vec![Vec3::ZERO, Vec3::X]).choose_branch(0.5), None);
+}
+
+#[test]
+fn test_update_exit_distances() {
+	let mut paths = vec![
+		Path::new(0, vec![Vec3::ZERO, Vec3::new(10., 0., 0.)])
+			.with_branch(1, 1.)
+			.with_branch(2, 1.),
+		// long way around
+		Path::new(1, vec![Vec3::new(10., 0., 0.), Vec3::new(10., 10., 0.), Vec3::new(20., 10., 0.), Vec3::new(20., 0., 0.)])
+			.with_branch(3, 1.),
+		// shortcut
+		Path::new(2, vec![Vec3::new(10., 0., 0.), Vec3::new(20., 0., 0.)])
+			.with_branch(3, 1.),
+		Path::new(3, vec![Vec3::new(20., 0., 0.), Vec3::new(25., 0., 0.)]),
+	];
+	update_exit_distances(&mut paths);
+	assert_eq!(paths[3].exit_distance, 0.);
+	assert_eq!(paths[2].exit_distance, 5.);
+	assert_eq!(paths[1].exit_distance, 5.);
+	assert_eq!(paths[0].exit_distance, 15.);
+	assert_eq!(paths[0].distance_to_exit(0.), 25.);
+	assert_eq!(paths[1].distance_to_exit(10.), 25.);
+}
+
+#[test]
+fn test_get_direction_along_path() {
+	let path = Path::new(
+		0,
+		vec![
+		Vec3::new(0.0, 0.0, 0.0),
+		Vec3::new(10.0, 0.0, 0.0),
+		Vec3::new(10.0, 10.0, 0.0),
+	]);
+	assert_eq!(path.get_direction_along_path(-1.), Vec3::X);
+	assert_eq!(path.get_direction_along_path(5.), Vec3::X);
+	assert_eq!(path.get_direction_along_path(15.), Vec3::Y);
+	assert_eq!(path.get_direction_along_path(100.), Vec3::Y);
+}
+
+#[test]
+fn test_catmull_rom_path() {
+	let points = vec![
+		Vec3::new(0.0, 0.0, 0.0),
+		Vec3::new(10.0, 0.0, 0.0),
+		Vec3::new(10.0, 10.0, 0.0),
+		Vec3::new(20.0, 10.0, 0.0),
+	];
+	let linear = Path::new(0, points.clone());
+	let curved = Path::new(0, points.clone()).with_interpolation(PathInterpolation::CatmullRom);
+	// the curve passes through every node
+	for (i, point) in points.iter().enumerate() {
+		assert!(curved.get_point_along_path(curved.node_distance(i)).distance(*point) < 0.001);
+	}
+	assert!(curved.get_point_along_path(curved.total_length()).abs_diff_eq(points[3], 1e-4));
+	assert_ne!(curved.total_length(), linear.total_length());
+	// distances are measured along the curve, so moving a set distance covers roughly that much ground
+	let a = curved.get_point_along_path(4.);
+	let b = curved.get_point_along_path(5.);
+	assert!((a.distance(b) - 1.).abs() < 0.01);
+	// the curve doesn't snap direction at the corners
+	let before_corner = curved.get_direction_along_path(curved.node_distance(1) - 0.1);
+	let after_corner = curved.get_direction_along_path(curved.node_distance(1) + 0.1);
+	assert!(before_corner.angle_between(after_corner) < 0.3);
+}
+
+#[test]
+fn test_get_point_along_path_out_of_range() {
+	let path = Path::new(
+		0,
+		vec![
+		Vec3::new(0.0, 0.0, 0.0),
+		Vec3::new(10.0, 0.0, 0.0),
+		Vec3::new(30.0, 0.0, 0.0),
+	]);
+	assert_eq!(path.get_point_along_path(10.), Vec3::new(10., 0., 0.));
+	assert_eq!(path.get_point_along_path(30.), Vec3::new(30., 0., 0.));
+	assert_eq!(path.get_point_along_path(50.), Vec3::new(30., 0., 0.));
+	assert_eq!(path.get_point_along_path_percent(1.), Vec3::new(30., 0., 0.));
+	assert_eq!(path.get_point_along_path_percent(-1.), Vec3::new(0., 0., 0.));
+	assert_eq!(path.get_point_along_path_percent(2.), Vec3::new(30., 0., 0.));
+}
+
+#[test]
+fn test_closest_distance() {
+	let path = Path::new(
+		0,
+		vec![
+		Vec3::new(0.0, 0.0, 0.0),
+		Vec3::new(10.0, 0.0, 0.0),
+		Vec3::new(10.0, 10.0, 0.0),
+	]);
+	assert_eq!(path.closest_distance(Vec3::new(4.0, 1.0, 0.0)), 4.);
+	assert_eq!(path.closest_distance(Vec3::new(12.0, 5.0, 0.0)), 15.);
+	assert_eq!(path.closest_distance(Vec3::new(-5.0, 0.0, 0.0)), 0.);
+}
+
+#[test]
+fn test_editing_path_points() {
+	let mut path = Path::new(
+		0,
+		vec![
+		Vec3::new(0.0, 0.0, 0.0),
+		Vec3::new(10.0, 0.0, 0.0),
+	]);
+	path.insert_point(1, Vec3::new(5.0, 5.0, 0.0));
+	assert_eq!(path.node_count(), 3);
+	assert_eq!(path.node_point(1), Vec3::new(5.0, 5.0, 0.0));
+	path.set_point(1, Vec3::new(5.0, 0.0, 0.0));
+	assert_eq!(path.total_length(), 10.);
+	assert_eq!(path.node_distance(1), 5.);
+	assert!(path.remove_point(1));
+	assert_eq!(path.node_count(), 2);
+	assert!(!path.remove_point(1));
+	assert_eq!(path.node_count(), 2);
+}
+
+#[test]
+fn test_empty_path() {
+	// broken levels can have paths without points, which shouldn't crash anything
+	let path = Path::new(0, vec![]);
+	assert_eq!(path.node_count(), 0);
+	assert_eq!(path.total_length(), 0.);
+}
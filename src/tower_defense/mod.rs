@@ -1,26 +1,44 @@
 use bevy::prelude::*;
-use bevy::ecs::event::Events;
+use bevy::asset::AssetServerSettings;
+use bevy::ecs::{event::Events, schedule::ShouldRun};
 
-mod player;
-mod towers;
-mod enemy;
-mod exp_level;
-mod map;
+pub mod player;
+pub mod towers;
+mod camera_focus;
+mod editor;
+pub mod enemy;
+pub mod exp_level;
+pub mod grid;
+mod inspector;
+pub mod level;
+pub mod map;
+pub mod motion;
+pub mod spatial;
+#[cfg(test)]
+mod scenario;
+mod settings;
 mod ui;
-mod waves;
+pub mod waves;
 
 use crate::camera::{self, PanOrbitCamera};
-use crate::tower_defense::waves::{Wave, WaveManager, WaveStage};
-use crate::pid_controller::{self, PidControlled};
+use crate::input::{Action, ActionState, InputActionPlugin, InputMap};
+use crate::tower_defense::waves::WaveManager;
+use crate::pid_controller;
 
-use self::enemy::{EnemyCreateOptions, EventEnemyDeath};
+use self::editor::EditorState;
+use self::enemy::{EventEnemyDeath, EventEnemyHurt, EventSpawnEnemy};
 use self::exp_level::{ExperienceBus, ExpLevel};
+use self::inspector::PidInspector;
+use self::level::{CurrentLevel, Level, LevelLoader};
+use self::settings::Settings;
 use player::Player;
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, SystemLabel)]
 enum SimulationStepLabel {
 	/// Core game logic. Eg. Movement, collision, etc.
 	Logic,
+	/// Record where things ended up and how they moved during the logic step.
+	Tracking,
 	Visual,
 	/// Reward the player for accomplishments.
 	Reward,
@@ -28,83 +46,145 @@ enum SimulationStepLabel {
 	Cleanup,
 }
 
+/// Where players can rebind the controls, relative to the assets folder. See [`InputMap`] for the format.
+const INPUT_CONFIG_PATH: &str = "input.ron";
+
+/// Whether the game is stopped. Toggled by [`Action::Pause`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Paused(pub bool);
+
 pub struct TowerDefensePlugin;
 
 impl Plugin for TowerDefensePlugin {
+	fn build(&self, app: &mut App) {
+		if !app.world.contains_resource::<CurrentLevel>() {
+			app.insert_resource(CurrentLevel::new("default"));
+		}
+
+		app
+			.add_plugin(TowerDefenseLogicPlugin)
+			.add_asset::<Level>()
+			.init_asset_loader::<LevelLoader>()
+			.insert_resource(Settings::default())
+			.init_resource::<EditorState>()
+			.init_resource::<PidInspector>()
+			.add_startup_system(load_input_map)
+			.add_startup_system(add_camera)
+			.add_startup_system(add_lights)
+			.add_startup_system(level::add_level_assets)
+			.add_startup_system(level::load_current_level)
+			.add_startup_system(grid::add_grid_assets)
+			.add_startup_system(ui::setup_ui)
+			.add_startup_system(ui::health_bar::setup_health_bars)
+			.add_startup_system(editor::setup_editor_ui)
+			.add_startup_system(inspector::setup_inspector_ui)
+			// in an earlier stage than the path lookup, so that new paths can be found right away
+			.add_system_to_stage(CoreStage::First, level::apply_level)
+			.add_system_to_stage(CoreStage::PostUpdate, level::sync_path_visuals)
+			.add_system(grid::place_grid_towers.before(SimulationStepLabel::Logic))
+			.add_system(grid::remove_unused_detours.after(SimulationStepLabel::Logic))
+			.add_system(map::visualize_path.label(SimulationStepLabel::Visual))
+			.add_system(ui::update_wave_text)
+			.add_system(ui::update_money_text)
+			.add_system(ui::update_lives_text)
+			.add_system(settings::toggle_settings)
+			.add_system(ui::health_bar::attach_health_bars)
+			.add_system(ui::health_bar::update_health_bars.after(SimulationStepLabel::Logic))
+			.add_system(ui::damage_numbers::spawn_damage_numbers.after(SimulationStepLabel::Logic))
+			.add_system(ui::damage_numbers::update_damage_numbers)
+			.add_plugin(camera::PanOrbitCameraPlugin)
+			.add_system(camera_focus::focus_camera.before(camera::CameraSystem::Input))
+			.add_system(editor::toggle_editor)
+			.add_system(editor::edit_paths)
+			.add_system(editor::paint_build_zones)
+			.add_system(editor::save_level)
+			.add_system(editor::highlight_selected_node.after(editor::edit_paths))
+			.add_system(inspector::toggle_inspector)
+			.add_system(inspector::inspect_controllers.after(pid_controller::drive_rotation::<towers::Aim>))
+			.add_system(inspector::update_inspector_ui.after(inspector::inspect_controllers));
+	}
+}
+
+/// The parts of the game that decide what happens, without any of the parts that draw it or let the player edit it.
+///
+/// Needs the mesh and material assets, and keyboard input for the [`Action`]s that start waves and pause,
+/// but no window or renderer, so the simulation can also be run headless.
+pub struct TowerDefenseLogicPlugin;
+
+impl Plugin for TowerDefenseLogicPlugin {
 	fn build(&self, app: &mut App) {
 		let expbus = ExperienceBus::new();
 		let deathbus: Events<EventEnemyDeath> = Events::default();
+		let spawnbus: Events<EventSpawnEnemy> = Events::default();
+		let hurtbus: Events<EventEnemyHurt> = Events::default();
 
 		app
-			.insert_resource(
-				WaveManager::new(
-					vec![
-						Wave {
-							stage: WaveStage::new(5, 0.25, EnemyCreateOptions {
-								health: 50,
-								speed: 5.,
-								path_id: 0,
-							}),
-						},
-						Wave {
-							stage: WaveStage::new(10, 0.5, EnemyCreateOptions {
-								health: 100,
-								speed: 3.,
-								path_id: 0,
-							}),
-						},
-						Wave {
-							stage: WaveStage::new(20, 0.20, EnemyCreateOptions {
-								health: 100,
-								speed: 10.,
-								path_id: 0,
-							}),
-						},
-						Wave {
-							stage: WaveStage::new(1000, 0.1, EnemyCreateOptions {
-								health: 30,
-								speed: 0.25,
-								path_id: 0,
-							}),
-						},
-					],
-				)
-			)
+			.insert_resource(WaveManager::new(vec![]))
 			.insert_resource(expbus)
 			.insert_resource(deathbus)
-			.add_startup_system(add_camera)
-			.add_startup_system(add_lights)
-			.add_startup_system(add_path)
-			.add_startup_system(add_towers)
+			.insert_resource(spawnbus)
+			.insert_resource(hurtbus)
+			.init_resource::<map::PathLookup>()
+			.init_resource::<spatial::SpatialHash>()
+			.init_resource::<Paused>()
+			.add_plugin(InputActionPlugin)
 			.add_startup_system(add_player)
-			.add_startup_system(ui::setup_ui)
-			.add_system(pid_controller::system_pid_controller_position)
-			.add_system(waves::spawn_enemies_from_waves)
+			.add_startup_system(towers::add_tower_assets)
+			.add_startup_system(enemy::add_enemy_prefabs)
+			.add_system_to_stage(CoreStage::PreUpdate, map::update_path_lookup)
+			.add_system(toggle_pause.before(SimulationStepLabel::Logic))
+			.add_system(
+				waves::spawn_enemies_from_waves
+					.with_run_criteria(simulation_running)
+					.before(SimulationStepLabel::Logic)
+			)
 			.add_system_set(
 				SystemSet::new()
 					.label(SimulationStepLabel::Logic)
+					.with_run_criteria(simulation_running)
 					.before(SimulationStepLabel::Reward)
+					.with_system(enemy::spawn_enemies)
 					.with_system(enemy::move_enemies)
+					.with_system(enemy::move_enemies_with_pid)
 					.with_system(enemy::monitor_health)
 			)
-			// .add_system(enemy::move_enemies_with_pid)
-			.add_system(towers::operate_towers)
-			.add_system(towers::tower_smooth_look.label(SimulationStepLabel::Visual))
+			.add_system(towers::operate_towers.with_run_criteria(simulation_running).after(SimulationStepLabel::Tracking))
+			// instead of the controllers' plugins, so that towers stop turning while the game is paused
+			.add_system_set(
+				SystemSet::new()
+					.with_run_criteria(simulation_running)
+					.with_system(pid_controller::drive::<pid_controller::Translation>)
+					.with_system(pid_controller::drive_rotation::<towers::Aim>)
+			)
 			.add_system_set(
 				SystemSet::new()
 					.label(SimulationStepLabel::Visual)
+					.with_run_criteria(simulation_running)
 					.with_system(towers::laser::aim_lasers)
 					.with_system(towers::laser::update_laser_locks)
 					.with_system(towers::laser::clean_up_expired_lasers)
-					.with_system(map::visualize_path)
 			)
-			.add_system(towers::projectile::move_projectiles)
-			.add_system(towers::projectile::projectile_collisions)
-			.add_system(towers::projectile::retarget_projectiles)
+			.add_system_set(
+				SystemSet::new()
+					.label(SimulationStepLabel::Tracking)
+					.with_run_criteria(simulation_running)
+					.after(SimulationStepLabel::Logic)
+					.with_system(motion::track_velocities)
+					.with_system(spatial::update_enemy_hash)
+			)
+			.add_system_set(
+				SystemSet::new()
+					.with_run_criteria(simulation_running)
+					.with_system(towers::projectile::move_projectiles.after(SimulationStepLabel::Tracking))
+					.with_system(towers::projectile::projectile_collisions.after(towers::projectile::move_projectiles))
+					.with_system(towers::projectile::retarget_projectiles)
+			)
 			.add_system_set(
 				SystemSet::new()
 					.label(SimulationStepLabel::Reward)
+					.with_run_criteria(simulation_running)
 					.with_system(enemy::process_enemy_death)
+					.with_system(enemy::process_enemy_escapes)
 					.with_system(towers::handle_tower_level_up)
 					.with_system(exp_level::process_experience_gain)
 					.with_system(exp_level::process_level_ups)
@@ -115,21 +195,43 @@ impl Plugin for TowerDefensePlugin {
 					.after(SimulationStepLabel::Reward)
 					.with_system(exp_level::update_exp_bus)
 					.with_system(Events::<EventEnemyDeath>::update_system)
-			)
-			.add_system(ui::update_wave_text)
-			.add_system(ui::update_money_text)
-			.add_system(camera::pan_orbit_camera);
+					.with_system(Events::<EventSpawnEnemy>::update_system)
+					.with_system(Events::<EventEnemyHurt>::update_system)
+			);
+	}
+}
+
+fn load_input_map(asset_settings: Option<Res<AssetServerSettings>>, mut input_map: ResMut<InputMap>) {
+	let file = level::asset_file_path(asset_settings.as_deref(), INPUT_CONFIG_PATH);
+	match InputMap::load(&file) {
+		Ok(loaded) => *input_map = loaded,
+		Err(err) => warn!("Using the default controls, couldn't load {}: {}", file.display(), err),
 	}
 }
 
+fn toggle_pause(actions: Res<ActionState>, mut paused: ResMut<Paused>) {
+	if actions.just_pressed(Action::Pause) {
+		paused.0 = !paused.0;
+		info!("Paused: {}", paused.0);
+	}
+}
+
+/// Run criteria for the systems that stop while the game is paused.
+fn simulation_running(paused: Res<Paused>) -> ShouldRun {
+	if paused.0 { ShouldRun::No } else { ShouldRun::Yes }
+}
+
 fn add_camera(mut commands: Commands) {
+	let camera = PanOrbitCamera::from_view(camera_focus::DEFAULT_VIEW)
+		// the map is laid out on the XY plane
+		.with_up(Vec3::Z)
+		.with_radius_limits(2.0, 100.0)
+		// from straight above down to almost level with the ground, but never below it
+		.with_pitch_limits(-std::f32::consts::FRAC_PI_2, -0.1);
 	commands.spawn_bundle(PerspectiveCameraBundle {
-		transform: Transform::from_xyz(0.0, 0.0, 20.0),
-		..Default::default()
-	}).insert(PanOrbitCamera {
-		radius: 20.0,
+		transform: camera.transform(),
 		..Default::default()
-	});
+	}).insert(camera);
 }
 
 fn add_lights(mut commands: Commands) {
@@ -165,120 +267,6 @@ fn add_lights(mut commands: Commands) {
 	});
 }
 
-fn add_path(
-	mut commands: Commands,
-	mut meshes: ResMut<Assets<Mesh>>,
-	mut materials: ResMut<Assets<StandardMaterial>>,
-) {
-	let path = map::Path::new(
-		0,
-		vec![
-		Vec3::new(-15.0, 0.0, 0.0),
-		Vec3::new(-5.0, 0.0, 0.0),
-		Vec3::new(-5.0, 0.0, 4.0),
-		Vec3::new(-5.0, -5.0, 5.0),
-		Vec3::new(0.0, -5.0, 5.0),
-		Vec3::new(0.0, 5.0, 5.0),
-		Vec3::new(5.0, 5.0, 5.0),
-		Vec3::new(5.0, 5.0, 0.0),
-		Vec3::new(0.0, 5.0, 0.0),
-		Vec3::new(-5.0, 2.0, 5.0),
-		Vec3::new(5.0, 0.0, 0.0),
-		Vec3::new(20.0, 0.0, 0.0),
-	]);
-	let mesh = meshes.add(Mesh::from(shape::UVSphere {
-		radius: 0.2,
-		sectors: 20,
-		stacks: 20,
-	}));
-	let material = materials.add(StandardMaterial {
-		base_color: Color::WHITE,
-		metallic: 1.,
-		perceptual_roughness: 0.5,
-		..Default::default()
-	});
-	for point in path.points() {
-		commands.spawn_bundle(
-			PbrBundle {
-				mesh: mesh.clone(),
-				material: material.clone(),
-				transform: Transform::from_translation(point),
-				..Default::default()
-			}
-		);
-	}
-	let visualizers = 5;
-	for i in 0..path.points().len() - 1 {
-		for j in 0..visualizers {
-			let mesh = meshes.add(Mesh::from(shape::UVSphere {
-				radius: 0.05,
-				sectors: 10,
-				stacks: 10,
-			}));
-			let material = materials.add(StandardMaterial {
-				base_color: Color::WHITE,
-				metallic: 1.,
-				perceptual_roughness: 0.5,
-				..Default::default()
-			});
-			commands.spawn_bundle(
-				PbrBundle {
-					mesh: mesh,
-					material: material,
-					..Default::default()
-				}
-			).insert(map::PathVisualizer {
-				path_id: path.id,
-				node_start: i,
-				node_end: i + 1,
-				offset: j as f32 / visualizers as f32,
-			});
-		}
-	}
-
-	commands.spawn()
-		.insert(path);
-}
-
-const PID_CONTROL_LOOK_AT: u64 = 1;
-
-fn add_towers(
-	mut commands: Commands,
-	mut meshes: ResMut<Assets<Mesh>>,
-	mut materials: ResMut<Assets<StandardMaterial>>,
-) {
-	let mesh = meshes.add(Mesh::from(shape::Cube { size: 1.0 }));
-	let material = materials.add(StandardMaterial {
-		base_color: Color::WHITE,
-		..Default::default()
-	});
-
-	let mut tower_positions = vec![
-		Vec3::from((-15., -5., 0.)),
-		Vec3::from((-10., -5., 0.)),
-	];
-
-	for i in 0..15  {
-		tower_positions.push(Vec3::from((15.0 - (i * 2) as f32, 3.0, 0.0)));
-	}
-
-	for pos in tower_positions {
-		let mut tower = towers::Tower::new();
-		tower.attack_type = towers::TowerAttackType::Laser;
-		commands.spawn_bundle(
-			PbrBundle {
-				mesh: mesh.clone(),
-				material: material.clone(),
-				transform: Transform::from_xyz(pos.x, pos.y, pos.z),
-				..Default::default()
-			}
-		)
-			.insert(PidControlled::<Vec3, PID_CONTROL_LOOK_AT>::new(0.1, 0., 0.01))
-			.insert(tower)
-			.insert(ExpLevel::new());
-	}
-}
-
 fn add_player(
 	mut commands: Commands,
 ) {
@@ -0,0 +1,108 @@
+use bevy::prelude::*;
+
+/// How many times the time of flight estimate is refined before giving up.
+const INTERCEPT_ITERATIONS: usize = 16;
+/// The time of flight estimate is good enough once it changes by less than this many seconds.
+const INTERCEPT_TOLERANCE: f32 = 0.001;
+
+/// Measures how fast an entity is moving, no matter what is moving it.
+#[derive(Component, Debug, Default, Clone, Copy)]
+pub struct TrackedVelocity {
+	/// Units per second, as of the last frame.
+	pub velocity: Vec3,
+	last_position: Option<Vec3>,
+}
+
+pub(crate) fn track_velocities(
+	time: Res<Time>,
+	mut query: Query<(&mut TrackedVelocity, &Transform)>,
+) {
+	let delta = time.delta_seconds();
+	for (mut tracked, transform) in query.iter_mut() {
+		if let Some(last_position) = tracked.last_position {
+			if delta > 0. {
+				tracked.velocity = (transform.translation - last_position) / delta;
+			}
+		}
+		tracked.last_position = Some(transform.translation);
+	}
+}
+
+/// How long it takes something leaving `origin` at `speed` to meet a target whose position after `t` seconds is `predict(t)`.
+///
+/// Starts by assuming the target stays still, then repeatedly re-aims at where the target will be once
+/// the previous estimate has passed. Returns `None` if the estimate doesn't settle, like when the target is
+/// running away faster than `speed`.
+pub fn intercept_time(origin: Vec3, speed: f32, predict: impl Fn(f32) -> Vec3) -> Option<f32> {
+	if speed <= 0. {
+		return None;
+	}
+	let mut time = origin.distance(predict(0.)) / speed;
+	for _ in 0..INTERCEPT_ITERATIONS {
+		let next_time = origin.distance(predict(time)) / speed;
+		if !next_time.is_finite() {
+			return None;
+		}
+		if (next_time - time).abs() < INTERCEPT_TOLERANCE {
+			return Some(next_time);
+		}
+		time = next_time;
+	}
+	None
+}
+
+/// Where to aim from `origin` to hit a target that keeps moving at a constant velocity.
+pub fn intercept_point(origin: Vec3, speed: f32, target_position: Vec3, target_velocity: Vec3) -> Option<Vec3> {
+	let predict = |time: f32| target_position + target_velocity * time;
+	intercept_time(origin, speed, predict).map(predict)
+}
+
+/// Velocity to launch something at from `origin`, so that it falls onto `target` after `flight_time` seconds.
+pub fn ballistic_launch_velocity(origin: Vec3, target: Vec3, flight_time: f32, gravity: Vec3) -> Vec3 {
+	(target - origin) / flight_time - gravity * flight_time / 2.
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_intercept_stationary_target() {
+		let target = Vec3::new(10., 0., 0.);
+		assert_eq!(intercept_time(Vec3::ZERO, 5., |_| target), Some(2.));
+		assert_eq!(intercept_point(Vec3::ZERO, 5., target, Vec3::ZERO), Some(target));
+	}
+
+	#[test]
+	fn test_intercept_moving_target() {
+		let target = Vec3::new(10., 0., 0.);
+		let velocity = Vec3::new(0., 2., 0.);
+		let speed = 10.;
+		let point = intercept_point(Vec3::ZERO, speed, target, velocity).unwrap();
+		// the projectile and the target arrive at the same time
+		let projectile_time = point.length() / speed;
+		let target_time = point.distance(target) / velocity.length();
+		assert!((projectile_time - target_time).abs() < 0.01);
+	}
+
+	#[test]
+	fn test_ballistic_launch_velocity() {
+		let origin = Vec3::new(1., 2., 0.);
+		let target = Vec3::new(6., -3., 1.);
+		let gravity = Vec3::new(0., 0., -10.);
+		let flight_time = 2.;
+		let velocity = ballistic_launch_velocity(origin, target, flight_time, gravity);
+		let landing = origin + velocity * flight_time + gravity * flight_time * flight_time / 2.;
+		assert!(landing.abs_diff_eq(target, 0.0001));
+		// goes up before coming back down
+		assert!(velocity.z > 0.);
+	}
+
+	#[test]
+	fn test_intercept_impossible() {
+		let target = Vec3::new(10., 0., 0.);
+		let velocity = Vec3::new(20., 0., 0.);
+		assert_eq!(intercept_point(Vec3::ZERO, 5., target, velocity), None);
+		assert_eq!(intercept_point(Vec3::ZERO, 0., target, Vec3::ZERO), None);
+	}
+}
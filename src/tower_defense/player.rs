@@ -3,6 +3,7 @@ use bevy::prelude::*;
 #[derive(Component, Debug, Default, Clone, Copy)]
 pub struct Player {
 	money: u64,
+	lives: u32,
 }
 
 impl Player {
@@ -10,6 +11,24 @@ impl Player {
 		Self { ..Default::default() }
 	}
 
+	pub fn with_money(mut self, money: u64) -> Self {
+		self.money = money;
+		self
+	}
+
+	pub fn with_lives(mut self, lives: u32) -> Self {
+		self.lives = lives;
+		self
+	}
+
+	pub fn lives(&self) -> u32 {
+		self.lives
+	}
+
+	pub fn lose_life(&mut self) {
+		self.lives = self.lives.saturating_sub(1);
+	}
+
 	pub fn money(&self) -> u64 {
 		self.money
 	}
@@ -42,4 +61,15 @@ mod test {
 		assert!(player.make_purchase(10).is_err());
 		assert_eq!(player.money(), 0);
 	}
+
+	#[test]
+	fn test_player_losing_lives() {
+		let mut player = Player::new().with_money(5).with_lives(2);
+		assert_eq!(player.money(), 5);
+		assert_eq!(player.lives(), 2);
+		player.lose_life();
+		player.lose_life();
+		player.lose_life();
+		assert_eq!(player.lives(), 0);
+	}
 }
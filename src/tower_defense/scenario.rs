@@ -0,0 +1,324 @@
+//! Plays out whole waves in a headless app, to check how the game's systems work together.
+
+use std::time::Duration;
+
+use bevy::{prelude::*, asset::AssetPlugin, core::{CorePlugin, CoreSystem}, ecs::system::CommandQueue};
+
+use super::{
+	enemy::{Enemy, EnemyCreateOptions, EnemyMovement},
+	exp_level::ExpLevel,
+	map,
+	player::Player,
+	towers::{self, Tower, TowerAssets, TowerAttackType},
+	waves::{Wave, WaveManager, WaveStage, WaveStatus},
+	TowerDefenseLogicPlugin,
+};
+
+/// How long every tick lasts.
+const FRAME_TIME: Duration = Duration::from_millis(16);
+
+/// The time that every tick sees, see [`hold_time`].
+struct FixedFrame(Time);
+
+/// `Time` can only be advanced by actually waiting, so it is measured over one real frame once,
+/// and then put back after every update. That way every tick lasts exactly as long.
+fn hold_time(frame: Res<FixedFrame>, mut time: ResMut<Time>) {
+	*time = frame.0.clone();
+}
+
+/// A game without a window, that can be set up and then run for as long as needed.
+struct Scenario {
+	app: App,
+}
+
+impl Scenario {
+	fn new() -> Self {
+		let mut frame = Time::default();
+		frame.update();
+		std::thread::sleep(FRAME_TIME);
+		frame.update();
+
+		let mut app = App::new();
+		app.add_plugin(CorePlugin)
+			.add_plugin(AssetPlugin)
+			.add_asset::<Mesh>()
+			.add_asset::<StandardMaterial>()
+			.init_resource::<Input<KeyCode>>()
+			.insert_resource(FixedFrame(frame))
+			.add_system_to_stage(CoreStage::First, hold_time.after(CoreSystem::Time))
+			.add_plugin(TowerDefenseLogicPlugin);
+		// run the startup systems
+		app.update();
+		Self { app }
+	}
+
+	fn with_player(mut self, player: Player) -> Self {
+		let mut players = self.app.world.query::<&mut Player>();
+		for mut existing in players.iter_mut(&mut self.app.world) {
+			*existing = player;
+		}
+		self
+	}
+
+	fn with_path(mut self, path: map::Path) -> Self {
+		self.app.world.spawn().insert(path);
+		self
+	}
+
+	fn with_tower(mut self, position: Vec3, attack_type: TowerAttackType) -> Self {
+		let mut queue = CommandQueue::default();
+		let mut commands = Commands::new(&mut queue, &self.app.world);
+		towers::spawn_tower(&mut commands, self.app.world.resource::<TowerAssets>(), position, attack_type);
+		queue.apply(&mut self.app.world);
+		self
+	}
+
+	/// Make every tower fire `attacks_per_second` times per second.
+	fn with_attack_rate(mut self, attacks_per_second: f32) -> Self {
+		let mut towers = self.app.world.query::<&mut Tower>();
+		for mut tower in towers.iter_mut(&mut self.app.world) {
+			tower.base_attack_rate = attacks_per_second;
+			tower.update_stats(0);
+		}
+		self
+	}
+
+	fn with_waves(mut self, waves: Vec<Wave>) -> Self {
+		self.app.world.insert_resource(WaveManager::new(waves));
+		self
+	}
+
+	fn tick_seconds(&self) -> f32 {
+		self.app.world.resource::<FixedFrame>().0.delta_seconds()
+	}
+
+	fn run_ticks(&mut self, ticks: usize) {
+		for _ in 0..ticks {
+			self.app.update();
+		}
+	}
+
+	fn run_for(&mut self, seconds: f32) {
+		let ticks = (seconds / self.tick_seconds()).ceil() as usize;
+		self.run_ticks(ticks);
+	}
+
+	/// Hold `key` down for a tick.
+	fn tap(&mut self, key: KeyCode) {
+		self.app.world.resource_mut::<Input<KeyCode>>().press(key);
+		self.run_ticks(1);
+		let mut input = self.app.world.resource_mut::<Input<KeyCode>>();
+		input.release(key);
+		input.clear();
+	}
+
+	/// Press space for a tick, which starts the next wave.
+	fn start_wave(&mut self) {
+		self.tap(KeyCode::Space);
+	}
+
+	/// Run until the current wave is over and the next one is waiting to be started, or `max_seconds` have passed.
+	/// Returns whether the wave finished.
+	fn run_wave(&mut self, max_seconds: f32) -> bool {
+		self.start_wave();
+		self.finish_wave(max_seconds)
+	}
+
+	/// Run until the wave that is going on is over, or `max_seconds` have passed. Returns whether the wave finished.
+	fn finish_wave(&mut self, max_seconds: f32) -> bool {
+		let wave = self.app.world.resource::<WaveManager>().current_wave_index;
+		let max_ticks = (max_seconds / self.tick_seconds()).ceil() as usize;
+		for _ in 0..max_ticks {
+			self.app.update();
+			let manager = self.app.world.resource::<WaveManager>();
+			if manager.current_wave_index > wave && matches!(manager.wave_status(), WaveStatus::Pending) {
+				return true;
+			}
+		}
+		false
+	}
+
+	fn player(&mut self) -> Player {
+		*self.app.world.query::<&Player>().iter(&self.app.world).next().expect("No player")
+	}
+
+	fn enemy_count(&mut self) -> usize {
+		self.app.world.query::<&Enemy>().iter(&self.app.world).count()
+	}
+
+	/// Every tower's (experience, level).
+	fn tower_experience(&mut self) -> Vec<(u64, u64)> {
+		self.app.world.query_filtered::<&ExpLevel, With<Tower>>()
+			.iter(&self.app.world)
+			.map(|level| (level.experience(), level.level()))
+			.collect()
+	}
+
+	fn entity_count(&self) -> u32 {
+		self.app.world.entities().len()
+	}
+}
+
+fn straight_path() -> map::Path {
+	map::Path::new(0, vec![Vec3::ZERO, Vec3::new(40., 0., 0.)])
+}
+
+fn wave(enemy_count: u32, health: u32, speed: f32) -> Wave {
+	moving_wave(enemy_count, health, speed, Default::default())
+}
+
+fn moving_wave(enemy_count: u32, health: u32, speed: f32, movement: EnemyMovement) -> Wave {
+	Wave {
+		stage: WaveStage::new(enemy_count, 0.5, EnemyCreateOptions {
+			health,
+			speed,
+			path_id: 0,
+			archetype: Default::default(),
+			movement,
+		}),
+	}
+}
+
+#[test]
+fn test_laser_towers_clear_wave() {
+	let mut scenario = Scenario::new()
+		.with_player(Player::new().with_lives(10))
+		.with_path(straight_path())
+		.with_tower(Vec3::new(10., 2., 0.), TowerAttackType::Laser)
+		.with_tower(Vec3::new(20., 2., 0.), TowerAttackType::Laser)
+		.with_waves(vec![wave(5, 30, 2.)]);
+	let entities = scenario.entity_count();
+
+	assert!(scenario.run_wave(60.));
+	assert_eq!(scenario.enemy_count(), 0);
+	let player = scenario.player();
+	assert_eq!(player.lives(), 10);
+	// one coin for every kill
+	assert_eq!(player.money(), 5);
+	// every enemy takes exactly two laser hits, and every hit is worth one experience
+	let experience = scenario.tower_experience();
+	assert_eq!(experience.iter().map(|(experience, _)| experience).sum::<u64>(), 10);
+	// the first tower does most of the work, and levels up because of it
+	assert!(experience.iter().any(|(_, level)| *level > 0));
+
+	// lasers fade out shortly after the last hit
+	scenario.run_for(1.);
+	assert_eq!(scenario.entity_count(), entities);
+}
+
+#[test]
+fn test_towers_fire_faster_than_the_frame_rate() {
+	let mut scenario = Scenario::new()
+		.with_path(straight_path())
+		.with_tower(Vec3::new(2., 2., 0.), TowerAttackType::Laser)
+		.with_attack_rate(200.)
+		.with_waves(vec![wave(1, 100_000, 0.5)]);
+	let hits = |scenario: &mut Scenario| -> u64 {
+		scenario.tower_experience().iter().map(|(experience, _)| experience).sum()
+	};
+	scenario.start_wave();
+	// give the tower time to turn towards the enemy
+	scenario.run_for(2.);
+	let before = hits(&mut scenario);
+	let ticks = 60;
+	scenario.run_ticks(ticks);
+	// a shot every 5ms, so about three shots every tick
+	let hits = hits(&mut scenario) - before;
+	assert!(hits > 2 * ticks as u64, "{} hits in {} ticks", hits, ticks);
+}
+
+#[test]
+fn test_projectile_towers_clear_wave() {
+	let mut scenario = Scenario::new()
+		.with_player(Player::new().with_lives(10))
+		.with_path(straight_path())
+		.with_tower(Vec3::new(8., 3., 0.), TowerAttackType::Projectile)
+		.with_tower(Vec3::new(16., -3., 0.), TowerAttackType::Mortar)
+		.with_tower(Vec3::new(24., 3., 0.), TowerAttackType::Projectile)
+		.with_waves(vec![wave(4, 30, 1.5)]);
+	let entities = scenario.entity_count();
+
+	assert!(scenario.run_wave(60.));
+	let player = scenario.player();
+	assert_eq!(player.lives(), 10);
+	assert_eq!(player.money(), 4);
+	assert!(scenario.tower_experience().iter().any(|(experience, _)| *experience > 0));
+
+	// shells still in the air land, and projectiles without anything left to chase disappear
+	scenario.run_for(5.);
+	assert_eq!(scenario.entity_count(), entities);
+}
+
+#[test]
+fn test_escaping_enemies_cost_lives() {
+	let mut scenario = Scenario::new()
+		.with_player(Player::new().with_lives(10).with_money(3))
+		.with_path(straight_path())
+		.with_waves(vec![wave(3, 10, 10.), wave(2, 10, 10.)]);
+	let entities = scenario.entity_count();
+
+	assert!(scenario.run_wave(30.));
+	assert_eq!(scenario.player().lives(), 7);
+	assert!(scenario.run_wave(30.));
+	let player = scenario.player();
+	assert_eq!(player.lives(), 5);
+	assert_eq!(player.money(), 3);
+	assert_eq!(scenario.entity_count(), entities);
+
+	// nothing happens once every wave is over
+	scenario.start_wave();
+	scenario.run_for(2.);
+	assert_eq!(scenario.enemy_count(), 0);
+}
+
+#[test]
+fn test_steered_enemies_follow_path() {
+	let mut scenario = Scenario::new()
+		.with_player(Player::new().with_lives(10))
+		.with_path(straight_path())
+		.with_waves(vec![moving_wave(3, 10, 5., EnemyMovement::Steered)]);
+
+	scenario.start_wave();
+	scenario.run_for(2.);
+	// on their way, but no faster than they are allowed to catch up
+	let mut enemies = scenario.app.world.query::<(&Enemy, &Transform)>();
+	for (enemy, transform) in enemies.iter(&scenario.app.world) {
+		assert!(transform.translation.x > 0. && transform.translation.x < 2. * 5. * 1.5);
+		assert!(transform.translation.distance(Vec3::new(enemy.path_pos, 0., 0.)) < 5.);
+	}
+
+	assert!(scenario.finish_wave(30.));
+	assert_eq!(scenario.player().lives(), 7);
+}
+
+#[test]
+fn test_pause() {
+	let mut scenario = Scenario::new()
+		.with_player(Player::new().with_lives(10))
+		.with_path(straight_path())
+		.with_tower(Vec3::new(2., -5., 0.), TowerAttackType::Laser)
+		.with_waves(vec![wave(3, 1000, 2.)]);
+	scenario.start_wave();
+	scenario.run_for(0.5);
+	let positions = |scenario: &mut Scenario| -> Vec<Vec3> {
+		scenario.app.world.query_filtered::<&Transform, With<Enemy>>()
+			.iter(&scenario.app.world)
+			.map(|transform| transform.translation)
+			.collect()
+	};
+	let tower_rotation = |scenario: &mut Scenario| -> Quat {
+		scenario.app.world.query_filtered::<&Transform, With<Tower>>().iter(&scenario.app.world).next().unwrap().rotation
+	};
+
+	scenario.tap(KeyCode::P);
+	let paused = positions(&mut scenario);
+	let rotation = tower_rotation(&mut scenario);
+	scenario.run_for(2.);
+	// nothing moves, turns or spawns
+	assert_eq!(positions(&mut scenario), paused);
+	assert_eq!(tower_rotation(&mut scenario), rotation);
+
+	scenario.tap(KeyCode::P);
+	scenario.run_for(1.);
+	assert_ne!(positions(&mut scenario), paused);
+}
@@ -0,0 +1,36 @@
+use bevy::prelude::*;
+
+use crate::input::{Action, ActionState};
+
+/// Player-facing display options.
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+	/// Show health bars above enemies.
+	pub show_health_bars: bool,
+	/// Show floating numbers when enemies take damage.
+	pub show_damage_numbers: bool,
+}
+
+impl Default for Settings {
+	fn default() -> Self {
+		Self {
+			show_health_bars: true,
+			show_damage_numbers: true,
+		}
+	}
+}
+
+/// Toggle health bars with [`Action::ToggleHealthBars`] (H), and damage numbers with [`Action::ToggleDamageNumbers`] (N).
+pub fn toggle_settings(
+	actions: Res<ActionState>,
+	mut settings: ResMut<Settings>,
+) {
+	if actions.just_pressed(Action::ToggleHealthBars) {
+		settings.show_health_bars = !settings.show_health_bars;
+		info!("Health bars: {}", settings.show_health_bars);
+	}
+	if actions.just_pressed(Action::ToggleDamageNumbers) {
+		settings.show_damage_numbers = !settings.show_damage_numbers;
+		info!("Damage numbers: {}", settings.show_damage_numbers);
+	}
+}
@@ -0,0 +1,122 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use super::enemy::Enemy;
+
+/// Buckets entities by position, so that finding everything near a point doesn't need to check every entity.
+/// The map is mostly flat, so entities are only bucketed by their X and Y coordinates.
+///
+/// Rebuilt every tick by [`update_enemy_hash`], after enemies have moved.
+#[derive(Debug)]
+pub struct SpatialHash {
+	cell_size: f32,
+	cells: HashMap<IVec2, Vec<(Entity, Vec3)>>,
+}
+
+impl Default for SpatialHash {
+	fn default() -> Self {
+		Self::new(5.)
+	}
+}
+
+impl SpatialHash {
+	/// `cell_size` should be around the size of the most common query radius.
+	pub fn new(cell_size: f32) -> Self {
+		Self {
+			cell_size,
+			cells: HashMap::default(),
+		}
+	}
+
+	fn cell(&self, position: Vec3) -> IVec2 {
+		(position.truncate() / self.cell_size).floor().as_ivec2()
+	}
+
+	/// Remove every entity, but keep the memory that was allocated for them.
+	pub fn clear(&mut self) {
+		for entities in self.cells.values_mut() {
+			entities.clear();
+		}
+	}
+
+	pub fn insert(&mut self, entity: Entity, position: Vec3) {
+		let cell = self.cell(position);
+		self.cells.entry(cell).or_default().push((entity, position));
+	}
+
+	pub fn len(&self) -> usize {
+		self.cells.values().map(Vec::len).sum()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.cells.values().all(Vec::is_empty)
+	}
+
+	/// Every entity within `radius` of `center`, along with where it was when it was inserted.
+	pub fn query_radius(&self, center: Vec3, radius: f32) -> impl Iterator<Item = (Entity, Vec3)> + '_ {
+		let min = self.cell(center - Vec3::splat(radius));
+		let max = self.cell(center + Vec3::splat(radius));
+		let radius_squared = radius * radius;
+		(min.y..=max.y)
+			.flat_map(move |y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+			.filter_map(|cell| self.cells.get(&cell))
+			.flatten()
+			.copied()
+			.filter(move |(_, position)| position.distance_squared(center) <= radius_squared)
+	}
+}
+
+/// Put every enemy into the [`SpatialHash`] at its current position.
+pub fn update_enemy_hash(
+	mut hash: ResMut<SpatialHash>,
+	enemies: Query<(Entity, &Transform), With<Enemy>>,
+) {
+	hash.clear();
+	for (entity, transform) in enemies.iter() {
+		hash.insert(entity, transform.translation);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_query_radius() {
+		let mut hash = SpatialHash::new(2.);
+		hash.insert(Entity::from_raw(0), Vec3::new(0.5, 0., 0.));
+		hash.insert(Entity::from_raw(1), Vec3::new(-3., 0., 0.));
+		hash.insert(Entity::from_raw(2), Vec3::new(10., 10., 10.));
+		hash.insert(Entity::from_raw(3), Vec3::new(1., 1., 1.));
+		let mut found: Vec<u32> = hash.query_radius(Vec3::ZERO, 3.).map(|(entity, _)| entity.id()).collect();
+		found.sort_unstable();
+		assert_eq!(found, vec![0, 1, 3]);
+		assert_eq!(hash.query_radius(Vec3::new(10., 10., 9.), 1.).count(), 1);
+		assert_eq!(hash.len(), 4);
+
+		hash.clear();
+		assert!(hash.is_empty());
+		assert_eq!(hash.query_radius(Vec3::ZERO, 100.).count(), 0);
+	}
+
+	#[test]
+	fn test_query_matches_brute_force() {
+		let mut hash = SpatialHash::new(1.5);
+		let points: Vec<Vec3> = (0..200)
+			.map(|i| {
+				let i = i as f32;
+				Vec3::new((i * 7.3) % 20. - 10., (i * 3.1) % 20. - 10., (i * 1.7) % 4.)
+			})
+			.collect();
+		for (i, point) in points.iter().enumerate() {
+			hash.insert(Entity::from_raw(i as u32), *point);
+		}
+		let center = Vec3::new(1., -2., 1.);
+		let mut found: Vec<u32> = hash.query_radius(center, 5.).map(|(entity, _)| entity.id()).collect();
+		found.sort_unstable();
+		let expected: Vec<u32> = points.iter().enumerate()
+			.filter(|(_, point)| point.distance(center) <= 5.)
+			.map(|(i, _)| i as u32)
+			.collect();
+		assert_eq!(found, expected);
+	}
+}
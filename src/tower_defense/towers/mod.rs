@@ -1,12 +1,14 @@
 use std::time::Duration;
 
-use bevy::prelude::*;
+use bevy::{prelude::*, ecs::event::ManualEventReader};
+use serde::{Deserialize, Serialize};
 
-use crate::{tower_defense::enemy, pid_controller::PidControlled};
+use crate::{tower_defense::{enemy::{self, EventEnemyHurt}, motion::TrackedVelocity}, pid_controller::PidControlledRotation};
 
 use self::{laser::{TowerLaser, TowerLaserLock}, projectile::TowerProjectile};
 
-use super::exp_level::{ExpLevel, ExperienceBus, EventExpGain};
+use super::exp_level::{ExpLevel, ExperienceBus, EventExpGain, EventLevelUp};
+use super::spatial::SpatialHash;
 
 pub mod laser;
 pub mod projectile;
@@ -20,11 +22,15 @@ pub struct Tower {
 	pub attack_timer: Timer,
 	pub targeting: TowerTargeting,
 	pub attack_type: TowerAttackType,
+	/// Whether the tower can attack enemies on the ground.
+	pub targets_ground: bool,
+	/// Whether the tower can attack flying enemies.
+	pub targets_air: bool,
 
-	/// The position the tower is currently looking at. Used for smoothly turning.
-	/// Changing the position that the tower is aiming at should be done through the
-	/// corresponding PidControlled component.
-	aim_position: Vec3,
+	/// How close to facing its target the tower has to be before it fires, in radians.
+	pub aim_tolerance: f32,
+	/// How many shots the tower has reloaded, which it fires as soon as it faces its target.
+	loaded: u32,
 }
 
 impl Tower {
@@ -45,6 +51,10 @@ impl Tower {
 		self.base_attack_rate + (level as f32 * 0.1)
 	}
 
+	pub fn can_target(&self, enemy: &enemy::Enemy) -> bool {
+		if enemy.is_flying() { self.targets_air } else { self.targets_ground }
+	}
+
 	/// Recalculate Tower stats based on level.
 	pub fn update_stats(&mut self, level: u64) {
 		self.attack_rate = self.attack_rate(level);
@@ -61,7 +71,10 @@ impl Default for Tower {
 			attack_timer: Timer::from_seconds(1.0, true),
 			targeting: TowerTargeting::default(),
 			attack_type: TowerAttackType::default(),
-			aim_position: Vec3::new(0., 0., 0.),
+			targets_ground: true,
+			targets_air: true,
+			aim_tolerance: 10f32.to_radians(),
+			loaded: 0,
 		}
 	}
 }
@@ -78,10 +91,12 @@ impl Default for TowerTargeting {
 	}
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TowerAttackType {
 	Laser,
 	Projectile,
+	/// Lobs explosive shells that hurt every enemy on the ground near where they land.
+	Mortar,
 }
 
 impl Default for TowerAttackType {
@@ -90,112 +105,182 @@ impl Default for TowerAttackType {
 	}
 }
 
-const PID_CONTROL_LOOK_AT: u64 = 1;
+/// Turns a [`Tower`] to face the enemy it is attacking.
+#[derive(Debug)]
+pub struct Aim;
+
+/// How fast towers turn, in radians per second.
+const TOWER_TURN_RATE: f32 = std::f32::consts::PI;
+
+/// Horizontal distance mortar shells cover per second. Far away targets take longer to hit.
+const MORTAR_SHELL_SPEED: f32 = 8.;
+/// Even close targets get a bit of an arc.
+const MORTAR_MIN_FLIGHT_TIME: f32 = 0.75;
+
+/// How much money it costs the player to build a tower.
+pub const TOWER_COST: u64 = 50;
+
+/// Mesh and material shared by all towers.
+pub struct TowerAssets {
+	mesh: Handle<Mesh>,
+	material: Handle<StandardMaterial>,
+}
 
+pub(crate) fn add_tower_assets(
+	mut commands: Commands,
+	mut meshes: ResMut<Assets<Mesh>>,
+	mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+	commands.insert_resource(TowerAssets {
+		mesh: meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
+		material: materials.add(StandardMaterial {
+			base_color: Color::WHITE,
+			..Default::default()
+		}),
+	});
+}
+
+/// Spawns a new tower in the world.
+pub fn spawn_tower(
+	commands: &mut Commands,
+	assets: &TowerAssets,
+	position: Vec3,
+	attack_type: TowerAttackType,
+) -> Entity {
+	let mut tower = Tower::new();
+	tower.attack_type = attack_type;
+	// projectiles are too slow to hit anything in the air
+	tower.targets_air = attack_type == TowerAttackType::Laser;
+	commands.spawn_bundle(
+		PbrBundle {
+			mesh: assets.mesh.clone(),
+			material: assets.material.clone(),
+			transform: Transform::from_translation(position),
+			..Default::default()
+		}
+	)
+		.insert(PidControlledRotation::<Aim>::new(10., 0., 0., TOWER_TURN_RATE))
+		.insert(tower)
+		.insert(ExpLevel::new())
+		.id()
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn operate_towers(
 	time: Res<Time>,
 	mut expbus: ResMut<ExperienceBus>,
-	mut towers: Query<(&mut Tower, &Transform, &mut PidControlled<Vec3, PID_CONTROL_LOOK_AT>, Entity)>,
-	mut enemy: Query<(&mut enemy::Enemy, &Transform, Entity), Without<Tower>>,
+	mut hurt_events: EventWriter<EventEnemyHurt>,
+	mut towers: Query<(&mut Tower, &Transform, &mut PidControlledRotation<Aim>, Entity)>,
+	mut enemy: Query<(&mut enemy::Enemy, &Transform, Entity, Option<&TrackedVelocity>), Without<Tower>>,
+	enemy_hash: Res<SpatialHash>,
 	mut commands: Commands,
 	mut meshes: ResMut<Assets<Mesh>>,
 	mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
 	for (mut tower, transform, mut controller, tower_entity) in towers.iter_mut() {
-		let mut enemies_in_range = enemy.iter_mut()
-			.filter(|e|
-				transform.translation.distance(e.1.translation) < tower.range && e.0.health > 0
-			)
-			.collect::<Vec<_>>();
+		let enemies_in_range = enemy_hash.query_radius(transform.translation, tower.range)
+			.filter_map(|(entity, position)| {
+				let (e, ..) = enemy.get(entity).ok()?;
+				(e.health > 0 && tower.can_target(e)).then_some((entity, position, e.distance_to_exit))
+			});
 		let target_enemy = match tower.targeting {
 			TowerTargeting::First => {
-				enemies_in_range.iter_mut().min_by(|a, b| {
-					b.0.path_pos.partial_cmp(&a.0.path_pos).unwrap()
+				enemies_in_range.min_by(|a, b| {
+					a.2.partial_cmp(&b.2).unwrap()
 				})
 			},
 			TowerTargeting::Closest => {
-				enemies_in_range.iter_mut().min_by(|a, b| {
-					transform.translation.distance(a.1.translation).partial_cmp(&transform.translation.distance(b.1.translation)).unwrap()
+				enemies_in_range.min_by(|a, b| {
+					transform.translation.distance(a.1).partial_cmp(&transform.translation.distance(b.1)).unwrap()
 				})
 			},
 		};
 
-		if let Some((enemy, enemy_pos, enemy_entity)) = target_enemy {
-			// make the tower look at the closest enemy
-			// transform.look_at(enemy_pos.translation, Vec3::new(0.0, 1.0, 0.0));
-			controller.set_target(enemy_pos.translation);
-
-			// tower attacks
-			for _ in 0..tower.attack_timer.tick(time.delta()).times_finished() {
-				match tower.attack_type {
-					TowerAttackType::Laser => {
-						enemy.hurt(15);
-						let radius = 0.1;
-						let mesh = meshes.add(Mesh::from(
-							shape::Capsule {
-								radius,
-								rings: 1,
-								depth: 1. - (radius * 2.),
-								latitudes: 5,
-								longitudes: 5,
-								uv_profile: shape::CapsuleUvProfile::Uniform,
-							}
-						));
-						let material = materials.add(StandardMaterial {
-							base_color: Color::GREEN,
-							..Default::default()
-						});
-						commands.spawn_bundle(
-							PbrBundle {
-								mesh,
-								material,
-								transform: transform.clone(),
+		if let Some((mut enemy, enemy_pos, enemy_entity, enemy_velocity)) = target_enemy.and_then(|(entity, ..)| enemy.get_mut(entity).ok()) {
+			// turn the tower to face the enemy
+			controller.set_target(transform.looking_at(enemy_pos.translation, Vec3::Y).rotation);
+
+			// hold on to reloaded shots until the tower faces the enemy, instead of reloading any more
+			if tower.loaded == 0 {
+				tower.loaded = tower.attack_timer.tick(time.delta()).times_finished();
+			}
+			// tower fires every reloaded shot once it is facing the enemy, so fast towers don't lose shots on long frames
+			if tower.loaded > 0 && controller.is_aimed(transform.rotation, tower.aim_tolerance) {
+				for _ in 0..std::mem::take(&mut tower.loaded) {
+					match tower.attack_type {
+						TowerAttackType::Laser => {
+							enemy.hurt(15);
+							hurt_events.send(EventEnemyHurt {
+								enemy: enemy_entity,
+								damage: 15,
+								position: enemy_pos.translation,
+							});
+							let radius = 0.1;
+							let mesh = meshes.add(Mesh::from(
+								shape::Capsule {
+									radius,
+									rings: 1,
+									depth: 1. - (radius * 2.),
+									latitudes: 5,
+									longitudes: 5,
+									uv_profile: shape::CapsuleUvProfile::Uniform,
+								}
+							));
+							let material = materials.add(StandardMaterial {
+								base_color: Color::GREEN,
 								..Default::default()
-							}
-						)
-							.insert(TowerLaser {
-								start_pos: transform.translation,
-								end_pos: enemy_pos.translation,
-								expire_timer: Timer::from_seconds(0.5, false),
-								override_expired: false,
-							})
-							.insert(TowerLaserLock {
-								source: tower_entity,
-								target: *enemy_entity,
 							});
-					},
-					TowerAttackType::Projectile => {
-						let proj = TowerProjectile::new(15, 10., *enemy_entity);
-						proj.spawn(transform.translation, transform.rotation, &mut commands, &mut meshes, &mut materials);
-					},
+							commands.spawn_bundle(
+								PbrBundle {
+									mesh,
+									material,
+									transform: transform.clone(),
+									..Default::default()
+								}
+							)
+								.insert(TowerLaser {
+									start_pos: transform.translation,
+									end_pos: enemy_pos.translation,
+									expire_timer: Timer::from_seconds(0.5, false),
+									override_expired: false,
+								})
+								.insert(TowerLaserLock {
+									source: tower_entity,
+									target: enemy_entity,
+								});
+						},
+						TowerAttackType::Projectile => {
+							let launch_velocity = (enemy_pos.translation - transform.translation).normalize_or_zero() * 5.;
+							let proj = TowerProjectile::guided(15, enemy_entity, launch_velocity);
+							proj.spawn(transform.translation, transform.rotation, &mut commands, &mut meshes, &mut materials);
+						},
+						TowerAttackType::Mortar => {
+							let distance = transform.translation.distance(enemy_pos.translation);
+							let flight_time = (distance / MORTAR_SHELL_SPEED).max(MORTAR_MIN_FLIGHT_TIME);
+							let velocity = enemy_velocity.map(|tracked| tracked.velocity).unwrap_or(Vec3::ZERO);
+							let landing_point = enemy_pos.translation + velocity * flight_time;
+							let proj = TowerProjectile::ballistic(25, enemy_entity, transform.translation, landing_point, flight_time, 2.);
+							proj.spawn(transform.translation, transform.rotation, &mut commands, &mut meshes, &mut materials);
+						},
+					}
+					expbus.experience_gain.send(EventExpGain{ entity: tower_entity, experience: 1 });
+					tower.attack_timer.reset();
 				}
-				expbus.experience_gain.send(EventExpGain{ entity: tower_entity, experience: 1 });
-				tower.attack_timer.reset();
 			}
 		}
 	}
 }
 
-pub fn tower_smooth_look(
-	time: Res<Time>,
-	mut towers: Query<(&mut Tower, &mut Transform, &mut PidControlled<Vec3, PID_CONTROL_LOOK_AT>)>,
-) {
-	for (mut tower, mut transform, mut controller) in towers.iter_mut() {
-		let aim = tower.aim_position;
-		tower.aim_position += controller.compute(time.delta_seconds(), aim);
-		transform.look_at(tower.aim_position, Vec3::new(0.0, 1.0, 0.0));
-	}
-}
-
 pub fn handle_tower_level_up(
 	expbus: Res<ExperienceBus>,
-	mut towers: Query<(&mut Tower, &ExpLevel), Changed<ExpLevel>>,
+	// kept between runs, so that level ups aren't applied again before the bus drops them
+	mut reader: Local<ManualEventReader<EventLevelUp>>,
+	mut towers: Query<&mut Tower, With<ExpLevel>>,
 ) {
-	let mut reader = expbus.level_up.get_reader();
 	for event in reader.iter(&expbus.level_up) {
 		let result = towers.get_mut(event.entity);
-		if let Ok((mut tower, tower_level)) = result {
-			tower.update_stats(tower_level.level());
+		if let Ok(mut tower) = result {
+			tower.update_stats(event.new_level);
 		}
 	}
 }
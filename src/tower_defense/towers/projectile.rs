@@ -2,24 +2,80 @@ use std::cmp::Ordering;
 
 use bevy::prelude::*;
 
-use crate::{tower_defense::{enemy::Enemy, map::Path}, pid_controller::PidControlled};
+use crate::tower_defense::{enemy::{Enemy, EventEnemyHurt}, motion::{self, TrackedVelocity}, spatial::SpatialHash};
+
+/// How close a guided projectile has to get to an enemy to hit it.
+const HIT_RADIUS: f32 = 0.5;
+
+/// Acceleration due to gravity for ballistic projectiles, in units per second squared.
+pub const GRAVITY: f32 = 9.8;
+
+/// Down is -Z.
+fn gravity() -> Vec3 {
+	-Vec3::Z * GRAVITY
+}
+
+/// How a projectile gets to its target.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProjectileMotion {
+	/// Steers towards where its target is going to be.
+	Guided {
+		/// Fastest the projectile can fly, in units per second.
+		max_speed: f32,
+		/// How quickly the projectile speeds up, in units per second squared.
+		acceleration: f32,
+		/// How quickly the projectile can change direction, in radians per second.
+		turn_rate: f32,
+	},
+	/// Launched in an arc that lands where its target is going to be, then explodes once its lifetime runs out.
+	Ballistic {
+		/// Enemies this close to where the projectile lands get hurt.
+		splash_radius: f32,
+	},
+}
 
 #[derive(Component, Debug)]
 pub struct TowerProjectile {
 	pub damage: u32,
-	pub speed: f32,
 	pub target: Entity,
-
-	last_pos: Option<Vec3>,
+	pub motion: ProjectileMotion,
+	/// Units per second.
+	pub velocity: Vec3,
+	/// The projectile disappears once this runs out.
+	pub lifetime: Timer,
+	/// The projectile disappears after getting this far away from where it was launched.
+	pub max_range: f32,
+	origin: Option<Vec3>,
 }
 
 impl TowerProjectile {
-	pub fn new(damage: u32, speed: f32, target: Entity) -> Self {
+	/// A projectile that homes in on `target`, starting off at `velocity`.
+	pub fn guided(damage: u32, target: Entity, velocity: Vec3) -> Self {
 		Self {
 			damage,
-			speed,
 			target,
-			last_pos: Default::default()
+			motion: ProjectileMotion::Guided {
+				max_speed: 10.,
+				acceleration: 20.,
+				turn_rate: std::f32::consts::PI * 2.,
+			},
+			velocity,
+			lifetime: Timer::from_seconds(5., false),
+			max_range: 40.,
+			origin: None,
+		}
+	}
+
+	/// A projectile launched from `origin` that falls onto `landing_point` after `flight_time` seconds.
+	pub fn ballistic(damage: u32, target: Entity, origin: Vec3, landing_point: Vec3, flight_time: f32, splash_radius: f32) -> Self {
+		Self {
+			damage,
+			target,
+			motion: ProjectileMotion::Ballistic { splash_radius },
+			velocity: motion::ballistic_launch_velocity(origin, landing_point, flight_time, gravity()),
+			lifetime: Timer::from_seconds(flight_time, false),
+			max_range: f32::INFINITY,
+			origin: None,
 		}
 	}
 
@@ -31,94 +87,165 @@ impl TowerProjectile {
 		meshes: &mut ResMut<Assets<Mesh>>,
 		materials: &mut ResMut<Assets<StandardMaterial>>,
 	) {
-		let mesh = meshes.add(Mesh::from(shape::Icosphere { radius: 0.1, subdivisions: 1 }));
+		let (radius, color) = match self.motion {
+			ProjectileMotion::Guided { .. } => (0.1, Color::BLUE),
+			ProjectileMotion::Ballistic { .. } => (0.25, Color::ORANGE),
+		};
+		let mesh = meshes.add(Mesh::from(shape::Icosphere { radius, subdivisions: 1 }));
 		let material = materials.add(StandardMaterial {
-			base_color: Color::BLUE,
+			base_color: color,
 			..Default::default()
 		});
 		let mut t = Transform::from_xyz(pos.x, pos.y, pos.z);
 		t.rotation = rot;
 		commands.spawn_bundle(
 			PbrBundle {
-				mesh: mesh,
-				material: material,
+				mesh,
+				material,
 				transform: t,
 				..Default::default()
 			}
 		)
 			.insert(self);
 	}
+
+	/// Change the velocity of a guided projectile to head towards `objective`, limited by how fast it can turn and speed up.
+	fn steer(&mut self, position: Vec3, objective: Vec3, delta: f32) {
+		if let ProjectileMotion::Guided { max_speed, acceleration, turn_rate } = self.motion {
+			let desired = (objective - position).normalize_or_zero();
+			let speed = (self.velocity.length() + acceleration * delta).min(max_speed);
+			let direction = if self.velocity == Vec3::ZERO {
+				desired
+			} else {
+				rotate_towards(self.velocity.normalize(), desired, turn_rate * delta)
+			};
+			self.velocity = direction * speed;
+		}
+	}
+}
+
+/// Turn the unit vector `from` towards `to` by no more than `max_angle` radians.
+fn rotate_towards(from: Vec3, to: Vec3, max_angle: f32) -> Vec3 {
+	if to == Vec3::ZERO {
+		return from;
+	}
+	let angle = from.angle_between(to);
+	if angle <= max_angle {
+		return to;
+	}
+	let axis = from.cross(to);
+	// pointing in the exact opposite direction, any axis will do
+	let axis = if axis.length_squared() > f32::EPSILON {
+		axis.normalize()
+	} else if from.x.abs() < 0.9 {
+		Vec3::X.cross(from).normalize()
+	} else {
+		Vec3::Y.cross(from).normalize()
+	};
+	Quat::from_axis_angle(axis, max_angle) * from
 }
 
 pub fn move_projectiles(
+	mut commands: Commands,
 	time: Res<Time>,
-	paths: Query<&Path>,
-	mut projectiles: Query<(&mut TowerProjectile, &mut Transform)>,
-	objects: Query<(&Transform, &Enemy), Without<TowerProjectile>>,
+	mut projectiles: Query<(Entity, &mut TowerProjectile, &mut Transform)>,
+	objects: Query<(&Transform, Option<&TrackedVelocity>), Without<TowerProjectile>>,
 ) {
-	for (mut projectile, mut transform) in projectiles.iter_mut() {
-		let result = objects.get(projectile.target);
-		if result.is_err() {
-			continue;
-		}
-		let (target, enemy) = result.unwrap();
-		// The target object's current position
-		let target_pos = target.translation;
-		// The approximate time in the future that the projectile will hit the target
-		let collision_time_delta = transform.translation.distance(target_pos) / projectile.speed;
-		let path = paths.iter()
-			.find(|path| path.id == enemy.path_id)
-			.expect(format!("No path with id: {}", enemy.path_id).as_str());
-		// The position to aim for. Here, we predict where the target will be in the future.
-		let predicted_pos = path.get_point_along_path(enemy.path_pos + enemy.speed * collision_time_delta);
-		let mut objective_pos = predicted_pos;
-
-		// HACK: if the projectile really close to the predicted position, it probably means
-		// that the projectile is jittering in place.
-		// Make a beeline for the actual target's position instead. Idk if this is a good idea.
-		if predicted_pos.distance(transform.translation) < 0.1 {
-			objective_pos = target_pos;
-		}
+	let delta = time.delta_seconds();
+	for (entity, mut projectile, mut transform) in projectiles.iter_mut() {
+		let origin = *projectile.origin.get_or_insert(transform.translation);
 
-		let current_velocity = if let Some(last_pos) = projectile.last_pos {
-			last_pos - transform.translation
-		} else {
-			Vec3::new(0.0, 0.0, 0.0)
-		};
-		let desired_velocity = (objective_pos - transform.translation).normalize() * projectile.speed;
-		let steering = desired_velocity - current_velocity;
+		match projectile.motion {
+			ProjectileMotion::Guided { max_speed, .. } => {
+				if let Ok((target, velocity)) = objects.get(projectile.target) {
+					// The target object's current position
+					let target_pos = target.translation;
+					let target_velocity = velocity.map(|tracked| tracked.velocity).unwrap_or(Vec3::ZERO);
+					// The position to aim for. Here, we predict where the target will be when the projectile reaches it.
+					// If it can't be reached, just chase it.
+					let objective_pos = motion::intercept_point(transform.translation, max_speed, target_pos, target_velocity)
+						.unwrap_or(target_pos);
+					let position = transform.translation;
+					projectile.steer(position, objective_pos, delta);
+				}
+			}
+			ProjectileMotion::Ballistic { .. } => {
+				projectile.velocity += gravity() * delta;
+			}
+		}
 
-		let new_velocity = current_velocity + steering;
-		let new_pos = transform.translation + new_velocity * time.delta().as_secs_f32();
-		transform.translation = new_pos;
+		transform.translation += projectile.velocity * delta;
+		if projectile.velocity != Vec3::ZERO {
+			let facing = transform.translation + projectile.velocity;
+			transform.look_at(facing, Vec3::Z);
+		}
 
-		projectile.last_pos = Some(transform.translation);
+		// ballistic projectiles explode when their time is up, see `projectile_collisions`
+		let expired = projectile.lifetime.tick(time.delta()).finished();
+		let is_guided = matches!(projectile.motion, ProjectileMotion::Guided { .. });
+		if is_guided && (expired || transform.translation.distance(origin) > projectile.max_range) {
+			commands.entity(entity).despawn();
+		}
 	}
 }
 
 pub fn projectile_collisions(
 	mut commands: Commands,
-	mut projectiles: Query<(Entity, &TowerProjectile, &Transform)>,
-	mut enemies: Query<(Entity, &mut Enemy, &Transform), Without<TowerProjectile>>,
+	mut hurt_events: EventWriter<EventEnemyHurt>,
+	projectiles: Query<(Entity, &TowerProjectile, &Transform)>,
+	mut enemies: Query<&mut Enemy, Without<TowerProjectile>>,
+	enemy_hash: Res<SpatialHash>,
 ) {
-	for (entity, mut projectile, transform) in projectiles.iter_mut() {
-		// check the projectile is close enough to ANY enemy
-		for (enemy_entity, mut enemy, enemy_transform) in enemies.iter_mut() {
-			if transform.translation.distance(enemy_transform.translation) < 0.5 {
+	for (entity, projectile, transform) in projectiles.iter() {
+		match projectile.motion {
+			ProjectileMotion::Guided { .. } => {
+				// check the projectile is close enough to ANY enemy
+				for (enemy_entity, enemy_position) in enemy_hash.query_radius(transform.translation, HIT_RADIUS) {
+					if let Ok(mut enemy) = enemies.get_mut(enemy_entity) {
+						commands.entity(entity).despawn();
+						enemy.hurt(projectile.damage);
+						hurt_events.send(EventEnemyHurt {
+							enemy: enemy_entity,
+							damage: projectile.damage,
+							position: enemy_position,
+						});
+						break;
+					}
+				}
+			}
+			ProjectileMotion::Ballistic { splash_radius } => {
+				if !projectile.lifetime.finished() {
+					continue;
+				}
 				commands.entity(entity).despawn();
-				enemy.hurt(projectile.damage);
-				break;
+				for (enemy_entity, enemy_position) in enemy_hash.query_radius(transform.translation, splash_radius) {
+					let mut enemy = match enemies.get_mut(enemy_entity) {
+						Ok(enemy) if !enemy.is_flying() => enemy,
+						_ => continue,
+					};
+					enemy.hurt(projectile.damage);
+					hurt_events.send(EventEnemyHurt {
+						enemy: enemy_entity,
+						damage: projectile.damage,
+						position: enemy_position,
+					});
+				}
 			}
 		}
 	}
 }
 
+/// Guided projectiles whose target is gone pick the closest enemy instead.
+#[allow(clippy::type_complexity)]
 pub fn retarget_projectiles(
 	mut commands: Commands,
 	mut projectiles: Query<(Entity, &mut TowerProjectile, &Transform)>,
 	mut enemies: Query<(Entity, &Transform), (With<Enemy>, Without<TowerProjectile>)>,
 ) {
 	for (entity, mut projectile, transform) in projectiles.iter_mut() {
+		if matches!(projectile.motion, ProjectileMotion::Ballistic { .. }) {
+			continue;
+		}
 		let result = enemies.get_mut(projectile.target);
 		if result.is_err() {
 			// retarget if there are more enemies
@@ -126,7 +253,7 @@ pub fn retarget_projectiles(
 				projectile.target = enemies.iter().min_by(|x, y| {
 					let x_dist = (x.1.translation - transform.translation).length();
 					let y_dist = (y.1.translation - transform.translation).length();
-					x_dist.partial_cmp(&y_dist).unwrap_or_else(|| Ordering::Equal)
+					x_dist.partial_cmp(&y_dist).unwrap_or(Ordering::Equal)
 				}).unwrap().0;
 			} else {
 				commands.entity(entity).despawn();
@@ -134,3 +261,39 @@ pub fn retarget_projectiles(
 		}
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_rotate_towards() {
+		let turned = rotate_towards(Vec3::X, Vec3::Y, std::f32::consts::FRAC_PI_4);
+		assert!(turned.abs_diff_eq(Vec3::new(1., 1., 0.).normalize(), 0.0001));
+		assert_eq!(rotate_towards(Vec3::X, Vec3::Y, std::f32::consts::PI), Vec3::Y);
+		let reversed = rotate_towards(Vec3::X, -Vec3::X, 0.1);
+		assert!((reversed.angle_between(Vec3::X) - 0.1).abs() < 0.0001);
+	}
+
+	#[test]
+	fn test_guided_projectile_turn_rate() {
+		let mut projectile = TowerProjectile::guided(1, Entity::from_raw(0), Vec3::X * 10.);
+		projectile.motion = ProjectileMotion::Guided {
+			max_speed: 10.,
+			acceleration: 0.,
+			turn_rate: 1.,
+		};
+		projectile.steer(Vec3::ZERO, Vec3::new(0., 10., 0.), 0.5);
+		assert!((projectile.velocity.angle_between(Vec3::X) - 0.5).abs() < 0.0001);
+		assert!((projectile.velocity.length() - 10.).abs() < 0.0001);
+	}
+
+	#[test]
+	fn test_guided_projectile_acceleration() {
+		let mut projectile = TowerProjectile::guided(1, Entity::from_raw(0), Vec3::ZERO);
+		projectile.steer(Vec3::ZERO, Vec3::X, 0.1);
+		assert!(projectile.velocity.abs_diff_eq(Vec3::X * 2., 0.0001));
+		projectile.steer(Vec3::ZERO, Vec3::X, 10.);
+		assert!(projectile.velocity.abs_diff_eq(Vec3::X * 10., 0.0001));
+	}
+}
@@ -0,0 +1,93 @@
+use bevy::{prelude::*, render::camera::Camera3d};
+
+use crate::tower_defense::{enemy::EventEnemyHurt, settings::Settings};
+
+/// How long damage numbers stay on screen, in seconds.
+const DAMAGE_NUMBER_LIFETIME: f32 = 0.8;
+/// How fast damage numbers float upwards, in units per second.
+const DAMAGE_NUMBER_RISE_SPEED: f32 = 1.5;
+
+/// Floating text showing how much damage an enemy took. Follows `world_pos` on screen, and fades out over time.
+#[derive(Component, Debug)]
+pub struct DamageNumber {
+	pub world_pos: Vec3,
+	pub timer: Timer,
+}
+
+pub(crate) fn spawn_damage_numbers(
+	settings: Res<Settings>,
+	asset_server: Res<AssetServer>,
+	mut commands: Commands,
+	mut hurt_events: EventReader<EventEnemyHurt>,
+) {
+	for event in hurt_events.iter() {
+		if !settings.show_damage_numbers {
+			continue;
+		}
+		commands
+			.spawn_bundle(TextBundle {
+				style: Style {
+					position_type: PositionType::Absolute,
+					..Style::default()
+				},
+				text: Text::with_section(
+					format!("{}", event.damage),
+					TextStyle {
+						font: asset_server.load("fonts/Hack-Regular.ttf"),
+						font_size: 20.0,
+						color: Color::YELLOW,
+					},
+					TextAlignment::default(),
+				),
+				// hidden until it has been positioned on screen
+				visibility: Visibility { is_visible: false },
+				..TextBundle::default()
+			})
+			.insert(DamageNumber {
+				world_pos: event.position,
+				timer: Timer::from_seconds(DAMAGE_NUMBER_LIFETIME, false),
+			});
+	}
+}
+
+pub(crate) fn update_damage_numbers(
+	time: Res<Time>,
+	settings: Res<Settings>,
+	windows: Res<Windows>,
+	images: Res<Assets<Image>>,
+	mut commands: Commands,
+	cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+	mut numbers: Query<(Entity, &mut DamageNumber, &mut Style, &mut Text, &mut Visibility)>,
+) {
+	let camera = cameras.iter().next();
+	for (entity, mut number, mut style, mut text, mut visibility) in numbers.iter_mut() {
+		if !settings.show_damage_numbers || number.timer.tick(time.delta()).finished() {
+			commands.entity(entity).despawn();
+			continue;
+		}
+		number.world_pos.y += DAMAGE_NUMBER_RISE_SPEED * time.delta_seconds();
+
+		let screen_pos = camera.and_then(|(camera, camera_transform)| {
+			camera.world_to_screen(&windows, &images, camera_transform, number.world_pos)
+		});
+		match screen_pos {
+			Some(screen_pos) => {
+				visibility.is_visible = true;
+				style.position = Rect {
+					left: Val::Px(screen_pos.x),
+					bottom: Val::Px(screen_pos.y),
+					..Default::default()
+				};
+			}
+			None => {
+				// behind the camera or otherwise off screen
+				visibility.is_visible = false;
+			}
+		}
+
+		let alpha = number.timer.percent_left();
+		for section in text.sections.iter_mut() {
+			section.style.color.set_a(alpha);
+		}
+	}
+}
@@ -0,0 +1,118 @@
+use bevy::{prelude::*, render::camera::Camera3d};
+
+use crate::tower_defense::{enemy::Enemy, settings::Settings};
+
+const HEALTH_BAR_WIDTH: f32 = 1.0;
+const HEALTH_BAR_HEIGHT: f32 = 0.12;
+/// How far above the enemy the health bar floats.
+const HEALTH_BAR_OFFSET: f32 = 1.0;
+
+/// A billboarded bar that floats above `target` and shows how much health it has left.
+#[derive(Component, Debug)]
+pub struct HealthBar {
+	pub target: Entity,
+}
+
+/// The filled portion of a [`HealthBar`]. Always a child of the bar.
+#[derive(Component, Debug)]
+pub struct HealthBarFill;
+
+/// Assets shared by all health bars.
+pub struct HealthBarAssets {
+	mesh: Handle<Mesh>,
+	background: Handle<StandardMaterial>,
+	fill: Handle<StandardMaterial>,
+}
+
+pub(crate) fn setup_health_bars(
+	mut commands: Commands,
+	mut meshes: ResMut<Assets<Mesh>>,
+	mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+	commands.insert_resource(HealthBarAssets {
+		mesh: meshes.add(Mesh::from(shape::Quad {
+			size: Vec2::new(HEALTH_BAR_WIDTH, HEALTH_BAR_HEIGHT),
+			flip: false,
+		})),
+		background: materials.add(StandardMaterial {
+			base_color: Color::rgb(0.1, 0.1, 0.1),
+			unlit: true,
+			..Default::default()
+		}),
+		fill: materials.add(StandardMaterial {
+			base_color: Color::LIME_GREEN,
+			unlit: true,
+			..Default::default()
+		}),
+	});
+}
+
+pub(crate) fn attach_health_bars(
+	mut commands: Commands,
+	assets: Res<HealthBarAssets>,
+	enemies: Query<Entity, Added<Enemy>>,
+) {
+	for enemy in enemies.iter() {
+		commands.spawn_bundle(
+			PbrBundle {
+				mesh: assets.mesh.clone(),
+				material: assets.background.clone(),
+				transform: Transform::from_xyz(-10000., 10000., 0.),
+				..Default::default()
+			}
+		)
+			.insert(HealthBar { target: enemy })
+			.with_children(|parent| {
+				parent.spawn_bundle(
+					PbrBundle {
+						mesh: assets.mesh.clone(),
+						material: assets.fill.clone(),
+						// slightly in front of the background so they don't z-fight
+						transform: Transform::from_xyz(0., 0., 0.01),
+						..Default::default()
+					}
+				)
+					.insert(HealthBarFill);
+			});
+	}
+}
+
+#[allow(clippy::type_complexity)]
+pub(crate) fn update_health_bars(
+	settings: Res<Settings>,
+	mut commands: Commands,
+	cameras: Query<&GlobalTransform, With<Camera3d>>,
+	mut bars: Query<(Entity, &HealthBar, &mut Transform, &mut Visibility, &Children)>,
+	mut fills: Query<(&mut Transform, &mut Visibility), (With<HealthBarFill>, Without<HealthBar>)>,
+	enemies: Query<(&Enemy, &Transform), (Without<HealthBar>, Without<HealthBarFill>)>,
+) {
+	let camera_rotation = cameras.iter().next().map(|camera| camera.rotation).unwrap_or_default();
+	for (entity, bar, mut transform, mut visibility, children) in bars.iter_mut() {
+		let (enemy, enemy_transform) = match enemies.get(bar.target) {
+			Ok(enemy) => enemy,
+			Err(_) => {
+				commands.entity(entity).despawn_recursive();
+				continue;
+			}
+		};
+
+		visibility.is_visible = settings.show_health_bars;
+		for child in children.iter() {
+			if let Ok((mut fill_transform, mut fill_visibility)) = fills.get_mut(*child) {
+				// visibility is not inherited, so the fill has to be hidden separately
+				fill_visibility.is_visible = settings.show_health_bars;
+				let health = enemy.health_percent();
+				fill_transform.scale.x = health;
+				// keep the bar anchored on the left side as it shrinks
+				fill_transform.translation.x = -(1. - health) * HEALTH_BAR_WIDTH / 2.;
+			}
+		}
+		if !settings.show_health_bars {
+			continue;
+		}
+
+		transform.translation = enemy_transform.translation + Vec3::Y * HEALTH_BAR_OFFSET;
+		// face the same way as the camera so the bar is always seen head on
+		transform.rotation = camera_rotation;
+	}
+}
@@ -1,5 +1,8 @@
 use bevy::prelude::*;
 
+pub(crate) mod damage_numbers;
+pub(crate) mod health_bar;
+
 use super::{waves::WaveStatus, player::Player};
 
 #[derive(Component)]
@@ -8,6 +11,9 @@ pub(crate) struct WaveText;
 #[derive(Component)]
 pub struct PlayerMoneyText;
 
+#[derive(Component)]
+pub struct PlayerLivesText;
+
 pub(crate) fn setup_ui(mut commands: Commands, asset_server: Res<AssetServer>) {
 	commands.spawn_bundle(UiCameraBundle::default());
 	commands
@@ -75,12 +81,52 @@ pub(crate) fn setup_ui(mut commands: Commands, asset_server: Res<AssetServer>) {
 			..TextBundle::default()
 		})
 		.insert(PlayerMoneyText);
+
+	commands
+		.spawn_bundle(TextBundle {
+			style: Style {
+				align_self: AlignSelf::FlexEnd,
+				..Style::default()
+			},
+			// Use `Text` directly
+			text: Text {
+				// Construct a `Vec` of `TextSection`s
+				sections: vec![
+					TextSection {
+						value: "Lives: ".to_string(),
+						style: TextStyle {
+							font: asset_server.load("fonts/Hack-Regular.ttf"),
+							font_size: 40.0,
+							color: Color::WHITE,
+						},
+					},
+					TextSection {
+						value: "".to_string(),
+						style: TextStyle {
+							font: asset_server.load("fonts/Hack-Regular.ttf"),
+							font_size: 40.0,
+							color: Color::WHITE,
+						},
+					},
+				],
+				..Text::default()
+			},
+			..TextBundle::default()
+		})
+		.insert(PlayerLivesText);
 }
 
-pub(crate) fn update_wave_text(wave_manager: Res<super::waves::WaveManager>, mut query: Query<&mut Text, With<WaveText>>) {
+pub(crate) fn update_wave_text(
+	wave_manager: Res<super::waves::WaveManager>,
+	paused: Res<super::Paused>,
+	mut query: Query<&mut Text, With<WaveText>>,
+) {
 	for mut text in query.iter_mut() {
 		// Update the value of the second section
 		text.sections[1].value = format!("{} / {}", wave_manager.current_wave_num(), wave_manager.waves.len());
+		if paused.0 {
+			text.sections[1].value.push_str(" (paused)");
+		}
 		match wave_manager.wave_status() {
 			WaveStatus::Pending => {
 				text.sections[1].style.color = Color::WHITE;
@@ -107,3 +153,13 @@ pub(crate) fn update_money_text(
 		text.sections[1].value = format!("{}", player.money());
 	}
 }
+
+pub(crate) fn update_lives_text(
+	player: Query<&Player>,
+	mut query: Query<&mut Text, With<PlayerLivesText>>
+) {
+	let player = player.single();
+	for mut text in query.iter_mut() {
+		text.sections[1].value = format!("{}", player.lives());
+	}
+}
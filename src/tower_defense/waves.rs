@@ -1,8 +1,9 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, ecs::event::Events};
 
+use crate::input::{Action, ActionState};
 use crate::tower_defense::enemy::Enemy;
 
-use super::enemy::EnemyCreateOptions;
+use super::enemy::{EnemyArchetype, EnemyCreateOptions, EnemyMovement, EventSpawnEnemy};
 
 #[derive(Component)]
 pub struct WaveManager {
@@ -67,6 +68,8 @@ impl Default for WaveStage {
 				health: 10,
 				speed: 3.0,
 				path_id: 0,
+				archetype: EnemyArchetype::Basic,
+				movement: EnemyMovement::Ground,
 			},
 
 			spawned: 0,
@@ -96,11 +99,9 @@ pub enum WaveStatus {
 
 pub fn spawn_enemies_from_waves(
 	time: Res<Time>,
-	keyboard_input: Res<Input<KeyCode>>,
+	actions: Res<ActionState>,
 	mut wave_manager: ResMut<WaveManager>,
-	mut commands: Commands,
-	mut meshes: ResMut<Assets<Mesh>>,
-	mut materials: ResMut<Assets<StandardMaterial>>,
+	mut spawn_requests: ResMut<Events<EventSpawnEnemy>>,
 	mut enemies: Query<(Entity, &Enemy), With<Enemy>>,
 ) {
 	if wave_manager.current_wave_index >= wave_manager.waves.len() {
@@ -109,7 +110,7 @@ pub fn spawn_enemies_from_waves(
 
 	match wave_manager.wave_status {
 		WaveStatus::Pending => {
-			if keyboard_input.pressed(KeyCode::Space) {
+			if actions.pressed(Action::StartWave) {
 				info!("Starting wave {}", wave_manager.current_wave_index);
 				wave_manager.enemy_spawn_timer = Timer::from_seconds(
 					wave_manager.current_wave().stage.spawn_rate, true
@@ -123,8 +124,9 @@ pub fn spawn_enemies_from_waves(
 				let wave = wave_manager.current_wave_mut();
 				if wave.stage.spawned < wave.stage.enemy_count {
 					wave.stage.spawned += 1;
-					let enemy = Enemy::new(wave.stage.enemy_create_options);
-					enemy.spawn(commands, meshes, materials);
+					spawn_requests.send(EventSpawnEnemy {
+						options: wave.stage.enemy_create_options,
+					});
 				} else {
 					wave_manager.set_wave_status(WaveStatus::WaitingForEnemiesToDie);
 				}